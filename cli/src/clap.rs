@@ -1,12 +1,14 @@
 use std::io::Write as _;
 
+use crate::command::ArgType;
 use crate::runfile::Runfile;
 use clap::{arg, Command};
 use clap_complete::shells::Shell;
 
-pub fn print_completion() {
-    // let app = build_cli(runfile);
-    // gen_completion(get_shell(), app, &mut std::io::stdout());
+/// Prints the completion script for the current shell.
+///
+/// If a runfile is available its commands, subcommands and aliases are completed too.
+pub fn print_completion(runfile: Option<&Runfile<'_>>) {
     let mut cmd = clap::Command::new("run").args([
         clap::Arg::new("help")
             .short('h')
@@ -16,6 +18,21 @@ pub fn print_completion() {
             .short('c')
             .long("commands")
             .help("Prints available commands in the runfile"),
+        clap::Arg::new("jobs")
+            .short('j')
+            .long("jobs")
+            .value_name("N")
+            .help("Runs up to N dependencies of the command at the same time"),
+        clap::Arg::new("yes")
+            .short('y')
+            .long("yes")
+            .help("Answers yes to the confirmations of the commands"),
+        clap::Arg::new("pick")
+            .long("pick")
+            .help("Picks the command to run from a list filtered as you type"),
+        clap::Arg::new("prompt")
+            .long("prompt")
+            .help("Asks for the missing arguments of the command"),
         clap::Arg::new("print-complete")
             .long("print-complete")
             .help("Prints the completion script for the current shell"),
@@ -25,9 +42,52 @@ pub fn print_completion() {
             .value_name("FILE")
             .help("Runs the specified file instead of searching for a runfile"),
     ]);
+    if let Some(runfile) = runfile {
+        cmd = cmd.subcommands(runfile_commands(runfile));
+    }
     clap_complete::generate(get_shell(), &mut cmd, "run", &mut std::io::stdout());
 }
 
+/// Builds a `clap::Command` for each command and subcommand in the runfile, recursively.
+fn runfile_commands(runfile: &Runfile<'_>) -> Vec<clap::Command> {
+    let commands = runfile.listed_commands().map(|c| {
+        clap::Command::new(c.name().to_string())
+            .about(c.doc_raw().to_owned())
+            .visible_aliases(c.aliases().iter().map(|a| a.to_string()))
+            .args(c.args().iter().map(|a| {
+                let arg = clap::Arg::new(a.name().to_string()).required(a.is_required());
+                // Choices and paths are completed with their values
+                match a.ty() {
+                    ArgType::Choices(_) => {
+                        arg.value_parser(clap::builder::PossibleValuesParser::new(
+                            a.choices()
+                                .map(|c| clap::builder::PossibleValue::new(c.to_string())),
+                        ))
+                    }
+                    ArgType::Path => arg.value_hint(clap::ValueHint::AnyPath),
+                    ArgType::Text | ArgType::Int => arg,
+                }
+            }))
+            .args(c.flags().iter().map(|f| {
+                let mut arg = clap::Arg::new(f.name().to_string());
+                if let Some(short) = f.short().and_then(|s| s.chars().next()) {
+                    arg = arg.short(short);
+                }
+                if let Some(long) = f.long() {
+                    arg = arg.long(long.to_string());
+                }
+                arg
+            }))
+    });
+    let subcommands = runfile.listed_subcommands().map(|sub| {
+        clap::Command::new(sub.name.to_string())
+            .about(sub.doc.clone())
+            .visible_aliases(sub.aliases.iter().map(|a| a.to_string()))
+            .subcommands(runfile_commands(sub))
+    });
+    commands.chain(subcommands).collect()
+}
+
 fn get_shell() -> Shell {
     let shell = std::process::Command::new("ps")
         .arg("-o")
@@ -49,23 +109,13 @@ fn get_shell() -> Shell {
 mod unused {
     use super::*;
     fn build_cli(runfile: &Runfile<'_>) -> clap::Command {
-        let commands = runfile.commands.iter().map(|(name, c)| {
-            clap::Command::new(name.to_string())
-                .about(c.doc_raw().to_owned())
-                .args(
-                    c.args()
-                        .iter()
-                        .map(|a| clap::Arg::new(a.to_string()).required(true)),
-                )
-        });
-
         clap::Command::new("run")
             .args([
                 arg!(-h --help "Prints help information"),
                 arg!(-c --commands "Prints available commands in the runfile"),
                 arg!(-f --file <FILE> "Runs the specified file instead of searching for a runfile"),
             ])
-            .subcommands(commands)
+            .subcommands(runfile_commands(runfile))
     }
 
     pub fn gen_completion(shell: Shell, mut app: Command, to: &mut impl std::io::Write) {
@@ -1,10 +1,11 @@
 pub use std::format as fmt;
-use std::io::Write;
+use std::io::{IsTerminal as _, Write};
 
 // use colored::{Color, Colorize as _};
 use yansi::{Color, Paint as _};
 
 use crate::{
+    error::Error,
     fmt::{
         strlist::{StrList, StrListSlice},
         Str,
@@ -12,54 +13,598 @@ use crate::{
     lang::{Lang, Language},
 };
 
+/// Values of the arguments and flags of a command, by name.
+type Values<'i> = Vec<(&'i str, Str<'i>)>;
+
+/// Arguments of a command checked by `Command::parse_args`, used by `Command::prepare`.
+#[derive(Debug, Clone, Default)]
+pub struct Arguments<'i> {
+    /// Value of each declared argument and flag.
+    values: Values<'i>,
+    /// Extra arguments passed to the program.
+    program: Vec<String>,
+}
+
+/// Which arguments `Command::parse_args` asks for, when stdin is a terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Prompt {
+    /// Only with `#[prompt]` or `RUN_PROMPT=1`.
+    #[default]
+    Off,
+    /// The missing ones, when some required argument is missing: `--prompt`
+    Missing,
+    /// Every argument and flag, with their defaults: the command picker
+    All,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind<'i> {
+    /// Must always be provided: `name`
+    Required,
+    /// Replaced with an empty value when omitted: `?name`
+    Optional,
+    /// Replaced with the specified value when omitted: `name="value"`
+    Default(&'i str),
+    /// Binds all the remaining arguments: `...name`
+    Rest,
+}
+
+/// Values accepted by an argument, written after its name: `replicas: int`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArgType<'i> {
+    /// Any value.
+    #[default]
+    Text,
+    /// 64 bit integer: `int`
+    Int,
+    /// Existing file or directory, made absolute because commands can run in other directories: `path`
+    Path,
+    /// One of the values separated by `|`: `dev|staging|prod`
+    Choices(&'i str),
+}
+
+impl std::fmt::Display for ArgType<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgType::Text => Ok(()),
+            ArgType::Int => write!(f, "int"),
+            ArgType::Path => write!(f, "path"),
+            ArgType::Choices(choices) => write!(f, "{choices}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arg<'i> {
+    name: &'i str,
+    kind: ArgKind<'i>,
+    ty: ArgType<'i>,
+}
+
+impl<'i> Arg<'i> {
+    pub fn new(name: &'i str, kind: ArgKind<'i>) -> Self {
+        Self {
+            name,
+            kind,
+            ty: ArgType::Text,
+        }
+    }
+
+    pub fn with_type(mut self, ty: ArgType<'i>) -> Self {
+        self.ty = ty;
+        self
+    }
+
+    pub fn ty(&self) -> ArgType<'i> {
+        self.ty
+    }
+
+    /// Values accepted by the argument, empty if it is not restricted to some choices.
+    pub fn choices(&self) -> impl Iterator<Item = &'i str> {
+        let choices = match self.ty {
+            ArgType::Choices(choices) => Some(choices),
+            _ => None,
+        };
+        choices.into_iter().flat_map(|c| c.split('|'))
+    }
+
+    /// Checks that `value` has the type of the argument, returning it as it should be used.
+    ///
+    /// Returns the reason it is not valid otherwise: `expected an integer`
+    pub fn validate(&self, value: String) -> Result<String, String> {
+        match self.ty {
+            ArgType::Text => Ok(value),
+            ArgType::Int => match value.parse::<i64>() {
+                Ok(_) => Ok(value),
+                Err(_) => Err("expected an integer".to_owned()),
+            },
+            ArgType::Path => match std::path::absolute(&value) {
+                Ok(path) if path.exists() => Ok(path.to_string_lossy().into_owned()),
+                _ => Err("expected an existing path".to_owned()),
+            },
+            ArgType::Choices(_) if self.choices().any(|c| c == value) => Ok(value),
+            ArgType::Choices(_) => Err(fmt!(
+                "expected one of [{}]",
+                StrList::from((", ", self.choices()))
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'i str {
+        self.name
+    }
+
+    pub fn kind(&self) -> ArgKind<'i> {
+        self.kind
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.kind == ArgKind::Required
+    }
+
+    /// Value used when the argument is not provided.
+    pub fn default_value(&self) -> Str<'i> {
+        match self.kind {
+            ArgKind::Default(value) => unescape(value),
+            ArgKind::Required | ArgKind::Optional | ArgKind::Rest => Str::borrowed(""),
+        }
+    }
+
+    /// Returns the argument as shown in the usage, `<NAME>`, `[NAME]` or `[NAME=value]`.
+    ///
+    /// The type is shown after the name: `<ENV: dev|prod>` or `<REPLICAS: int>`
+    pub fn usage(&self) -> String {
+        let name = match self.ty {
+            ArgType::Text => self.name.to_uppercase(),
+            ty => fmt!("{}: {ty}", self.name.to_uppercase()),
+        };
+        match self.kind {
+            ArgKind::Required => fmt!("<{name}>"),
+            ArgKind::Optional => fmt!("[{name}]"),
+            ArgKind::Default(value) => fmt!("[{name}={value}]"),
+            ArgKind::Rest => fmt!("[{name}...]"),
+        }
+    }
+}
+
+/// Named flag or option of a command: `--release`, `-v` or `-j/--jobs=4`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flag<'i> {
+    short: Option<&'i str>,
+    long: Option<&'i str>,
+    /// `None` if the flag does not take a value.
+    default: Option<&'i str>,
+}
+
+impl<'i> Flag<'i> {
+    pub fn new(short: Option<&'i str>, long: Option<&'i str>, default: Option<&'i str>) -> Self {
+        Self {
+            short,
+            long,
+            default,
+        }
+    }
+
+    pub fn short(&self) -> Option<&'i str> {
+        self.short
+    }
+
+    pub fn long(&self) -> Option<&'i str> {
+        self.long
+    }
+
+    /// Name used in the interpolation, the long name if available.
+    pub fn name(&self) -> &'i str {
+        self.long.or(self.short).unwrap_or_default()
+    }
+
+    pub fn takes_value(&self) -> bool {
+        self.default.is_some()
+    }
+
+    /// Returns `true` if `key` is one of `-short` or `--long`.
+    pub fn matches(&self, key: &str) -> bool {
+        key.strip_prefix("--").is_some_and(|k| Some(k) == self.long)
+            || key.strip_prefix('-').is_some_and(|k| Some(k) == self.short)
+    }
+
+    /// Value used when the flag is not provided.
+    pub fn default_value(&self) -> Str<'i> {
+        self.default.map(unescape).unwrap_or(Str::borrowed("false"))
+    }
+
+    /// Returns the flag as written in the signature, `-j/--jobs=4`.
+    pub fn signature(&self) -> String {
+        let keys = match (self.short, self.long) {
+            (Some(short), Some(long)) => fmt!("-{short}/--{long}"),
+            (Some(short), None) => fmt!("-{short}"),
+            (None, long) => fmt!("--{}", long.unwrap_or_default()),
+        };
+        match self.default {
+            Some(default) => fmt!("{keys}={default}"),
+            None => keys,
+        }
+    }
+
+    /// Returns the flag as shown in the usage, `[--release]` or `[-j/--jobs=4]`.
+    pub fn usage(&self) -> String {
+        fmt!("[{}]", self.signature())
+    }
+}
+
+/// Command that must be run before another one: `cmd deploy() : build sub.test {}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dependency<'i> {
+    name: &'i str,
+    start: usize,
+    end: usize,
+    /// Name and contents of the included file it is written in, the span is relative to it.
+    file: Option<(&'i str, &'i str)>,
+}
+
+impl<'i> Dependency<'i> {
+    pub fn new(name: &'i str, start: usize, end: usize) -> Self {
+        Self {
+            name,
+            start,
+            end,
+            file: None,
+        }
+    }
+
+    /// Returns `error`, about this dependency, with the included file it is written in.
+    pub fn error(&self, error: Error) -> Error {
+        match self.file {
+            Some((name, input)) => {
+                Error::PIncluded(Box::new(error), name.to_owned(), input.to_owned())
+            }
+            None => error,
+        }
+    }
+
+    /// Path of the command, with subcommands separated by '.'.
+    pub fn name(&self) -> &'i str {
+        self.name
+    }
+
+    pub fn span(&self) -> (usize, usize) {
+        (self.start, self.end)
+    }
+}
+
+/// Metadata written before a command or subcommand: `#[cwd = "src", export]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Attribute<'i> {
+    /// Directory the command runs in: `#[cwd = "src"]`
+    Cwd(&'i str),
+    /// Passes arguments and constants as environment variables: `#[export]`
+    Export,
+    /// Requirement to be available: `#[os(linux)]`
+    Condition(Condition<'i>),
+    /// Not listed in the help or completions, like the names starting with `_`: `#[hidden]`
+    Hidden,
+    /// Asks before running the command, with a custom question or a default one: `#[confirm("Sure?")]`
+    Confirm(Option<&'i str>),
+    /// Asks for the missing arguments instead of failing, when stdin is a terminal: `#[prompt]`
+    Prompt,
+}
+
+/// Requirement for a command or subcommand to be available: `#[os(linux)]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition<'i> {
+    /// Any of the operating systems or families (`unix`, `windows`).
+    Os(Vec<&'i str>),
+    /// Any of the CPU architectures.
+    Arch(Vec<&'i str>),
+    /// All of the programs are in the PATH.
+    Which(Vec<&'i str>),
+    /// The environment variable is set and not empty, or the opposite if negated: `#[if(!env.CI)]`
+    Env { name: &'i str, negated: bool },
+}
+
+impl Condition<'_> {
+    pub fn holds(&self) -> bool {
+        use std::env::consts::{ARCH, FAMILY, OS};
+        match self {
+            Condition::Os(os) => os.iter().any(|&o| o == OS || o == FAMILY),
+            Condition::Arch(arch) => arch.contains(&ARCH),
+            Condition::Which(programs) => programs.iter().all(|p| which::which(p).is_ok()),
+            Condition::Env { name, negated } => {
+                std::env::var_os(name).is_some_and(|v| !v.is_empty()) != *negated
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Condition<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |name: &str, items: &[&str], f: &mut std::fmt::Formatter<'_>| {
+            write!(
+                f,
+                "{name}({})",
+                StrList::from((", ", items.iter().copied()))
+            )
+        };
+        match self {
+            Condition::Os(os) => list("os", os, f),
+            Condition::Arch(arch) => list("arch", arch, f),
+            Condition::Which(programs) => list("which", programs, f),
+            Condition::Env { name, negated } => {
+                write!(f, "if({}env.{name})", if *negated { "!" } else { "" })
+            }
+        }
+    }
+}
+
+/// Value of a constant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Var<'i> {
+    Text(Str<'i>),
+    /// Evaluated by the shell in `dir` the first time a command uses it: ``const sha = `git rev-parse HEAD` ``
+    Shell {
+        script: &'i str,
+        dir: std::path::PathBuf,
+        value: std::sync::OnceLock<String>,
+    },
+}
+
+impl<'i> Var<'i> {
+    pub fn shell(script: &'i str, dir: std::path::PathBuf) -> Self {
+        Var::Shell {
+            script,
+            dir,
+            value: std::sync::OnceLock::new(),
+        }
+    }
+
+    /// Returns the value, evaluating it if needed.
+    pub fn value(&self) -> Result<Str<'_>, Str<'static>> {
+        match self {
+            Var::Text(text) => Ok(Str::borrowed(text)),
+            Var::Shell { script, dir, value } => {
+                if let Some(value) = value.get() {
+                    return Ok(Str::borrowed(value));
+                }
+                let output = evaluate_shell(script, dir)?;
+                Ok(Str::borrowed(value.get_or_init(|| output)))
+            }
+        }
+    }
+}
+
 #[derive(Eq, Clone)]
 pub struct Command<'i> {
     name: &'i str,
+    aliases: Vec<&'i str>,
     doc: String,
     lang: Lang,
-    args: Vec<&'i str>,
+    args: Vec<Arg<'i>>,
+    flags: Vec<Flag<'i>>,
+    deps: Vec<Dependency<'i>>,
+    cwd: Option<std::path::PathBuf>,
+    /// Passes arguments and constants as environment variables instead of replacing them in the script.
+    export: bool,
+    conditions: Vec<Condition<'i>>,
+    /// Not listed in the help or completions: `#[hidden]`
+    hidden: bool,
+    /// Question asked before running the command: `#[confirm]`
+    confirm: Option<Str<'i>>,
+    /// Asks for the missing arguments: `#[prompt]`
+    prompt: bool,
     script: &'i str,
 }
 
 impl<'i> Command<'i> {
-    // TODO: Add way to specify working directory
     pub fn new(
         name: &'i str,
         doc: String,
         lang: Lang,
-        args: Vec<&'i str>,
+        args: Vec<Arg<'i>>,
+        flags: Vec<Flag<'i>>,
         script: &'i str,
     ) -> Self {
         Self {
             name,
+            aliases: Vec::new(),
             doc,
             lang,
             args,
+            flags,
+            deps: Vec::new(),
+            cwd: None,
+            export: false,
+            conditions: Vec::new(),
+            hidden: false,
+            confirm: None,
+            prompt: false,
             script,
         }
     }
 
+    pub fn with_aliases(mut self, aliases: Vec<&'i str>) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    pub fn with_dependencies(mut self, deps: Vec<Dependency<'i>>) -> Self {
+        self.deps = deps;
+        self
+    }
+
+    /// Sets the directory the command runs in.
+    pub fn with_cwd(mut self, cwd: std::path::PathBuf) -> Self {
+        self.cwd = Some(cwd);
+        self
+    }
+
+    pub fn with_export(mut self, export: bool) -> Self {
+        self.export = export;
+        self
+    }
+
+    /// Applies the attributes written before the command, `cwd` is relative to the current one.
+    pub fn with_attributes(mut self, attributes: Vec<Attribute<'i>>) -> Self {
+        for attribute in attributes {
+            match attribute {
+                Attribute::Cwd(cwd) => {
+                    self.cwd = Some(self.cwd.take().unwrap_or_default().join(cwd))
+                }
+                Attribute::Export => self.export = true,
+                Attribute::Condition(c) => self.conditions.push(c),
+                Attribute::Hidden => self.hidden = true,
+                Attribute::Confirm(question) => {
+                    self.confirm = Some(match question {
+                        Some(question) => question.into(),
+                        None => Str::owned(fmt!("Run '{}'?", self.name)),
+                    })
+                }
+                Attribute::Prompt => self.prompt = true,
+            }
+        }
+        self
+    }
+
+    /// Adds requirements for the command to be available, like the ones of its subcommand.
+    pub fn add_conditions(&mut self, conditions: &[Condition<'i>]) {
+        self.conditions.extend_from_slice(conditions);
+    }
+
+    /// Hidden commands can be called and used as dependencies, but are not listed.
+    pub fn is_hidden(&self) -> bool {
+        self.name.starts_with('_') || self.hidden
+    }
+
+    /// Returns `true` if arguments should be asked for, with `#[prompt]`, `--prompt`, `RUN_PROMPT=1` or the picker.
+    fn prompts(&self, prompt: Prompt) -> bool {
+        (prompt != Prompt::Off || self.prompt || crate::utils::env_enabled("RUN_PROMPT"))
+            && std::io::stdin().is_terminal()
+    }
+
+    /// Asks for the value of each missing argument, using the default value when the answer is empty.
+    ///
+    /// With `Prompt::All` the values of the rest argument are asked for one at a time, until the answer is empty.
+    fn prompt_missing(&self, args: &mut Vec<String>, prompt: Prompt) {
+        for arg in self.args.iter().skip(args.len()) {
+            if arg.kind == ArgKind::Rest {
+                if prompt != Prompt::All {
+                    return;
+                }
+                while let Some(value) = prompt_arg(arg).filter(|v| !v.is_empty()) {
+                    args.push(value);
+                }
+                return;
+            }
+            // End of input, keep the arguments collected so far
+            let Some(value) = prompt_arg(arg) else {
+                return;
+            };
+            args.push(value);
+        }
+    }
+
+    /// Asks for the value of each flag, keeping the default value when the answer is empty.
+    ///
+    /// Flags without a value are enabled by answering 'y' or 'yes'.
+    fn prompt_flags(&self, values: &mut Values<'i>) {
+        for (flag, (_, value)) in self.flags.iter().zip(values) {
+            let key = match flag.long {
+                Some(long) => fmt!("--{long}"),
+                None => fmt!("-{}", flag.short.unwrap_or_default()),
+            };
+            let hint = match flag.default {
+                Some(default) => fmt!(" [{default}]"),
+                None => " [y/N]".to_owned(),
+            };
+            eprint!("{}{}: ", key.bright_cyan().bold(), hint.dim());
+            let mut answer = String::new();
+            if std::io::stdin().read_line(&mut answer).unwrap_or_default() == 0 {
+                eprintln!();
+                return;
+            }
+            let answer = answer.trim();
+            match flag.takes_value() {
+                _ if answer.is_empty() => {}
+                true => *value = Str::owned(answer.to_owned()),
+                false if matches!(answer.to_lowercase().as_str(), "y" | "yes") => {
+                    *value = Str::borrowed("true")
+                }
+                false => {}
+            }
+        }
+    }
+
+    /// Returns the question to ask before running the command, if it needs confirmation.
+    pub fn confirmation(&self) -> Option<Str<'i>> {
+        self.confirm.clone()
+    }
+
+    /// Returns the first requirement that is not met, if the command is unavailable.
+    pub fn unmet_condition(&self) -> Option<&Condition<'i>> {
+        self.conditions.iter().find(|c| !c.holds())
+    }
+
+    /// Sets the included file the dependencies are written in, unless they come from a file it includes.
+    pub fn set_file(&mut self, name: &'i str, input: &'i str) {
+        for dep in &mut self.deps {
+            dep.file.get_or_insert((name, input));
+        }
+    }
+
+    /// Returns the requirements for the command to be available.
+    pub fn conditions(&self) -> &[Condition<'i>] {
+        &self.conditions
+    }
+
     pub fn name(&self) -> &'i str {
         self.name
     }
 
+    pub fn aliases(&self) -> &[&'i str] {
+        &self.aliases
+    }
+
+    /// Returns the name followed by the aliases, as shown in the list of commands: `build, b`.
+    pub fn names(&self) -> String {
+        StrList::from((
+            ", ",
+            std::iter::once(self.name).chain(self.aliases.iter().copied()),
+        ))
+        .to_string()
+    }
+
     pub fn lang(&self) -> Lang {
         self.lang
     }
 
-    pub fn args(&self) -> &[&'i str] {
+    pub fn args(&self) -> &[Arg<'i>] {
         &self.args
     }
 
+    pub fn flags(&self) -> &[Flag<'i>] {
+        &self.flags
+    }
+
+    pub fn dependencies(&self) -> &[Dependency<'i>] {
+        &self.deps
+    }
+
+    /// Number of arguments that must always be provided.
+    pub fn required_args(&self) -> usize {
+        self.args.iter().filter(|a| a.is_required()).count()
+    }
+
     // Clippy does not detect the usage in the 'format!' macro
     #[allow(unused_variables)]
     pub fn usage(&self, parents: StrListSlice, color: Color, newlines: usize) -> String {
         let usage = "Usage:".paint(color).bold();
         let parents = parents.bright_cyan().bold();
         let name = self.name.bright_cyan().bold();
-        let args = self.args.iter().fold(String::new(), |acc, a| {
-            acc + "<" + &a.to_uppercase() + ">" + " "
-        });
+        let args = self
+            .flags
+            .iter()
+            .map(Flag::usage)
+            .chain(self.args.iter().map(Arg::usage))
+            .fold(String::new(), |acc, a| acc + &a + " ");
         let args = args.cyan();
         if name.value == "default" {
             return format!("{usage} {parents} {args}{}", "\n".repeat(newlines));
@@ -89,6 +634,33 @@ impl<'i> Command<'i> {
             writeln!(to, "{:indent$}{l}", "")?;
         }
 
+        if self.flags.is_empty() {
+            return Ok(());
+        }
+        writeln!(to, "\n{:indent$}{}", "", "Options:".bright_green().bold())?;
+        let flags = self.flags.iter().map(|f| {
+            let short = f.short.map(|s| fmt!("-{s},")).unwrap_or_default();
+            let long = f.long.map(|l| fmt!("--{l}")).unwrap_or_default();
+            let value = f.default.map(|_| fmt!(" <{}>", f.name().to_uppercase()));
+            (short, long + &value.unwrap_or_default(), f.default)
+        });
+        let width = flags
+            .clone()
+            .map(|(_, l, _)| l.len())
+            .max()
+            .unwrap_or_default();
+        for (short, long, default) in flags {
+            let default = default.map(|d| fmt!(" [default: {d}]")).unwrap_or_default();
+            writeln!(
+                to,
+                "{:indent$}  {:>3} {:width$}{}",
+                "",
+                short.bright_cyan().bold(),
+                long.bright_cyan().bold(),
+                default.dim()
+            )?;
+        }
+
         Ok(())
     }
 
@@ -103,116 +675,521 @@ impl<'i> Command<'i> {
         script.map(|l| &l[indent..]).collect::<Vec<_>>().join("\n")
     }
 
+    /// Runs the command with arguments checked by `parse_args`.
+    ///
+    /// Exits if it fails, so no command depending on this one is run.
     pub fn run(
         &self,
         parents: StrListSlice,
-        args: impl AsRef<[String]>,
-        vars: impl AsRef<[(&'i str, Str<'i>)]>,
+        args: &Arguments<'i>,
+        vars: &[(&'i str, Var<'i>)],
+        env: &[(&'i str, Str<'i>)],
         runfile_docs: String,
-    ) -> std::io::Result<()> {
-        let args = args.as_ref();
-        let vars = vars.as_ref();
-        let name = self.name;
-        if args.iter().any(|a| a == "--help" || a == "-h") {
-            self.print_help(parents, 0, &mut std::io::stdout())?;
-            return Ok(());
+    ) {
+        let result = self
+            .prepare(parents, args, vars, env, runfile_docs)
+            .and_then(crate::lang::spawn_and_wait);
+        if let Err(e) = result {
+            self.print_error(parents, e);
+            std::process::exit(1);
         }
+    }
 
-        if args.len() < self.args.len() {
-            let expected = StrList::from((
-                ", ",
-                self.args.iter().map(|a| format!("<{}>", a.to_uppercase())),
-            ));
-            let got = StrList::from((", ", args.iter().map(|a| a.as_str())));
-            eprintln!(
-                "{}{parents} {name}: Expected arguments [{expected}], got [{got}]{}",
-                "".bright_red().bold().linger(),
-                "".clear()
-            );
-            eprintln!(
-                "See '{}{parents} {name} --help{}' for more information",
+    /// Prints the error of a failed execution of the command.
+    pub fn print_error(&self, parents: StrListSlice, error: impl std::fmt::Display) {
+        eprintln!(
+            "{}{} {}{}\n",
+            "Error running '".bright_red().bold(),
+            parents.magenta().bold(),
+            self.name.magenta().bold(),
+            "':".bright_red().bold()
+        );
+        eprintln!("{error}");
+    }
+
+    /// Returns an error in the arguments of the command, followed by where to read its usage if `help` is `true`.
+    fn args_error(
+        &self,
+        parents: StrListSlice,
+        msg: impl std::fmt::Display,
+        help: bool,
+    ) -> Str<'static> {
+        let name = self.name;
+        let mut error = fmt!(
+            "{}{parents} {name}: {msg}{}",
+            "".bright_red().bold().linger(),
+            "".clear()
+        );
+        if help {
+            error += &fmt!(
+                "\nSee '{}{parents} {name} --help{}' for more information",
                 "".bright_cyan().bold(),
                 "".clear()
             );
-            std::process::exit(1);
         }
+        Str::owned(error)
+    }
 
-        // Remove indentation from script
-        let script = replace_all(
-            self.script_with_indent_fix(),
-            (&self.args, &args[..self.args.len()]),
-            vars,
-            runfile_docs,
-            self.doc(parents).to_string(),
-            self.usage(parents, Color::White, 0),
-        );
-        let args = args.get(self.args.len()..).unwrap_or(&[]);
-        // Run the script
-        if let Err(e) = self.lang.execute(&script, args) {
-            eprintln!(
-                "{}{} {}{}\n",
-                "Error running '".bright_red().bold(),
-                parents.magenta().bold(),
-                name.magenta().bold(),
-                "':".bright_red().bold()
+    /// Checks that the command is available and that `args` are valid, asking for the missing ones if `prompt` is `true`.
+    ///
+    /// Runs before any dependency, the returned error is ready to be printed.
+    pub fn parse_args(
+        &self,
+        parents: StrListSlice,
+        args: &[String],
+        prompt: Prompt,
+    ) -> Result<Arguments<'i>, Str<'static>> {
+        if let Some(condition) = self.unmet_condition() {
+            let msg = fmt!("Only available with {condition}");
+            return Err(self.args_error(parents, msg, false));
+        }
+
+        let (mut args, mut flags) = self
+            .parse_flags(args)
+            .map_err(|e| self.args_error(parents, e, true))?;
+
+        if self.prompts(prompt) {
+            if args.len() < self.required_args() || prompt == Prompt::All {
+                self.prompt_missing(&mut args, prompt);
+            }
+            if prompt == Prompt::All {
+                self.prompt_flags(&mut flags);
+            }
+        }
+        if args.len() < self.required_args() {
+            let expected = StrList::from((", ", self.args.iter().map(Arg::usage)));
+            let got = StrList::from((", ", args.iter().map(|a| a.as_str())));
+            let msg = fmt!("Expected arguments [{expected}], got [{got}]");
+            return Err(self.args_error(parents, msg, true));
+        }
+
+        let invalid = |arg: &Arg, value: &str, reason: String| {
+            let msg = fmt!(
+                "Invalid value '{value}' for argument {}, {reason}",
+                arg.usage()
             );
-            eprintln!("{e}");
+            self.args_error(parents, msg, false)
+        };
+        let rest = self.args.last().filter(|a| a.kind == ArgKind::Rest);
+        for (i, value) in args.iter_mut().enumerate() {
+            let Some(arg) = self.args.get(i).or(rest) else {
+                break;
+            };
+            *value = arg
+                .validate(value.clone())
+                .map_err(|reason| invalid(arg, value, reason))?;
         }
 
-        Ok(())
+        // A rest argument consumes everything, otherwise extra arguments are passed to the program
+        let provided = if rest.is_some() {
+            args.len()
+        } else {
+            args.len().min(self.args.len())
+        };
+        // Omitted arguments are replaced with their default value, which is validated too so paths are absolute
+        let mut values = Vec::with_capacity(self.args.len() + flags.len());
+        for (i, a) in self.args.iter().enumerate() {
+            let value = match (a.kind, args.get(i)) {
+                (ArgKind::Rest, _) => Str::owned(self.lang.list(&args[i.min(provided)..provided])),
+                (_, Some(value)) => Str::owned(value.clone()),
+                (ArgKind::Default(_), None) => {
+                    let default = a.default_value().into_owned();
+                    let valid = a
+                        .validate(default.clone())
+                        .map_err(|reason| invalid(a, &default, reason))?;
+                    Str::owned(valid)
+                }
+                (_, None) => a.default_value(),
+            };
+            values.push((a.name, value));
+        }
+        values.extend(flags);
+
+        // Exported commands get the rest arguments as arguments of the program too
+        let passed = match rest {
+            Some(_) if self.export => (self.args.len() - 1).min(provided),
+            _ => provided,
+        };
+        Ok(Arguments {
+            values,
+            program: args.split_off(passed.min(args.len())),
+        })
     }
+
+    /// Interpolates the arguments into the script, or exports them, and prepares the process that runs it.
+    pub fn prepare(
+        &self,
+        parents: StrListSlice,
+        args: &Arguments<'i>,
+        vars: &[(&'i str, Var<'i>)],
+        env: &[(&'i str, Str<'i>)],
+        runfile_docs: String,
+    ) -> Result<std::process::Command, Str<'static>> {
+        let script = self.script_with_indent_fix();
+        let mut constants = Vec::with_capacity(vars.len());
+        for (name, var) in vars {
+            // Evaluating a shell constant is expensive, skip the ones the command can't use
+            if matches!(var, Var::Shell { .. }) && !uses_name(&script, name) {
+                continue;
+            }
+            let value = var
+                .value()
+                .map_err(|e| Str::owned(fmt!("Failed to evaluate constant '{name}'\n\n{e}")))?;
+            constants.push((*name, Str::owned(expand_env(&value, env).into_owned())));
+        }
+        let vars = constants;
+
+        let script = if self.export {
+            // The script is left untouched, the arguments are exported instead
+            script
+        } else {
+            replace_all(
+                script,
+                &args.values,
+                &vars,
+                self.lang,
+                runfile_docs,
+                self.doc(parents).to_string(),
+                self.usage(parents, Color::White, 0),
+            )
+        };
+        let mut program = self
+            .lang
+            .prepare(&script, &args.program)
+            .map_err(|e| Str::owned(e.into_owned()))?;
+        // Languages that must run in a specific directory set it themselves
+        if let (Some(cwd), None) = (&self.cwd, program.get_current_dir()) {
+            program.current_dir(cwd);
+        }
+        if self.export {
+            program.envs(
+                vars.iter()
+                    .map(|(name, value)| (name, unescape(value).into_owned())),
+            );
+        }
+        program.envs(
+            env.iter()
+                .map(|(name, value)| (name, unescape(value).into_owned())),
+        );
+        if self.export {
+            let rest = self.args.iter().find(|a| a.kind == ArgKind::Rest);
+            let args = args
+                .values
+                .iter()
+                .filter(|(name, _)| rest.is_none_or(|r| r.name != *name))
+                .map(|(name, value)| (arg_env_name(name), value.as_ref()));
+            program.envs(args);
+        }
+        Ok(program)
+    }
+
+    /// Separates the declared flags from the positional arguments.
+    ///
+    /// Everything after `--` is considered positional.
+    fn parse_flags(&self, args: &[String]) -> Result<(Vec<String>, Values<'i>), String> {
+        // Without flags everything is passed to the program, like '--foo' in 'run cmd --foo'
+        if self.flags.is_empty() {
+            return Ok((args.to_vec(), Vec::new()));
+        }
+        let mut positional = Vec::new();
+        let mut values = vec![None; self.flags.len()];
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                positional.extend(args.cloned());
+                break;
+            }
+            // Negative numbers are valid positional arguments
+            if !arg.starts_with('-') || arg.len() == 1 || arg.parse::<f64>().is_ok() {
+                positional.push(arg.clone());
+                continue;
+            }
+            let (key, inline) = match arg.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            let Some(i) = self.flags.iter().position(|f| f.matches(key)) else {
+                return Err(fmt!("Unknown flag '{key}'"));
+            };
+            let value = match (self.flags[i].takes_value(), inline) {
+                (false, None) => "true".to_owned(),
+                (false, Some(_)) => return Err(fmt!("Flag '{key}' does not take a value")),
+                (true, Some(value)) => value.to_owned(),
+                (true, None) => args
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| fmt!("Expected a value for '{key}'"))?,
+            };
+            values[i] = Some(Str::owned(value));
+        }
+
+        let flags = self
+            .flags
+            .iter()
+            .zip(values)
+            .map(|(f, v)| (f.name(), v.unwrap_or_else(|| f.default_value())));
+        Ok((positional, flags.collect()))
+    }
+}
+
+/// Replaces escape sequences like `\n` or `\$` with the characters they represent.
+fn unescape(value: &str) -> Str<'_> {
+    let patterns = ["\\n", "\\r", "\\t", "\\0", "\\\"", "\\'", "\\\\", "\\$"];
+    let replace_with = ["\n", "\r", "\t", "\0", "\"", "'", "\\", "$"];
+    let ac = aho_corasick::AhoCorasick::new(patterns).unwrap();
+    ac.replace_all(value, &replace_with).into()
+}
+
+/// Returns the environment variable an exported argument is available in: `RUN_ARG_<NAME>`.
+fn arg_env_name(name: &str) -> String {
+    format!("RUN_ARG_{}", name.to_uppercase().replace('-', "_"))
+}
+
+/// Runs `script` with the shell in `dir`, returning its output escaped like the value of a constant.
+fn evaluate_shell(script: &str, dir: &std::path::Path) -> Result<String, Str<'static>> {
+    let shell = Lang::from(crate::lang::Shell);
+    let mut program = shell
+        .prepare(script, [] as [String; 0])
+        .map_err(|e| Str::owned(e.into_owned()))?;
+    let output = program
+        .current_dir(dir)
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .map_err(|e| crate::lang::execution_failed(shell.binary(), e))?;
+    crate::lang::exit_status(output.status)?;
+    // Like '$(...)' in shells, the trailing newlines are removed
+    let output = String::from_utf8_lossy(&output.stdout);
+    Ok(output
+        .trim_end_matches('\n')
+        .replace('\\', "\\\\")
+        .replace('$', "\\$"))
+}
+
+/// Replaces every `$env.NAME` in the value of a constant with the variable declared with `env`,
+/// or with the one `run` was called with.
+fn expand_env<'a>(value: &'a str, env: &[(&str, Str<'_>)]) -> Str<'a> {
+    if !value.contains("$env.") {
+        return Str::borrowed(value);
+    }
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(i) = rest.find("$env.") {
+        let escaped = rest[..i].ends_with('\\');
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i + "$env.".len()..];
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        let (name, after) = rest.split_at(len);
+        rest = after;
+        if escaped || name.is_empty() {
+            expanded.push_str("$env.");
+            expanded.push_str(name);
+            continue;
+        }
+        match env.iter().rev().find(|(n, _)| *n == name) {
+            Some((_, v)) => expanded.push_str(v),
+            // Escaped, as the value is unescaped with the rest of the constant
+            None => expanded.push_str(
+                &std::env::var(name)
+                    .unwrap_or_default()
+                    .replace('\\', "\\\\"),
+            ),
+        }
+    }
+    expanded.push_str(rest);
+    Str::owned(expanded)
+}
+
+/// Returns the shell command that calls `run` on the current runfile, the value of `$run`.
+///
+/// Uses the `RUN_EXE` and `RUNFILE` environment variables, set before running any command.
+fn run_invocation() -> String {
+    let quote = |s: String| {
+        let safe = |c: char| c.is_ascii_alphanumeric() || "/._-+:,@".contains(c);
+        if s.chars().all(safe) {
+            s
+        } else {
+            crate::lang::single_quote(&s)
+        }
+    };
+    let exe = quote(std::env::var("RUN_EXE").unwrap_or_else(|_| "run".into()));
+    match std::env::var("RUNFILE") {
+        Ok(file) => format!("{exe} -f {}", quote(file)),
+        Err(_) => exe,
+    }
+}
+
+/// Returns the choice numbered `answer`, starting at 1, or `answer` itself if it is not a number.
+///
+/// Numbers that are already choices are kept: `10|20`.
+fn choice_answer<'a>(choices: &[&'a str], answer: &'a str) -> &'a str {
+    if choices.contains(&answer) {
+        return answer;
+    }
+    answer
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| choices.get(n.wrapping_sub(1)))
+        .copied()
+        .unwrap_or(answer)
+}
+
+/// Whether `name` is used whole when followed by `after`, names are replaced whole and '$run' is not used in '$runner'.
+pub(crate) fn is_whole_name(name: &str, after: &str) -> bool {
+    let continues = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    !name.ends_with(continues) || !after.starts_with(continues)
 }
 
+/// Asks for the value of `arg` until the answer is valid, an empty answer gives the default value.
+///
+/// Choices can be answered with their number, unless the number is a choice too.
+/// Returns `None` at the end of input.
+fn prompt_arg(arg: &Arg<'_>) -> Option<String> {
+    let choices = arg.choices().collect::<Vec<_>>();
+    let numbered = choices
+        .iter()
+        .enumerate()
+        .map(|(i, c)| fmt!("{}) {c}", i + 1));
+    let numbered = StrList::from((", ", numbered));
+    let (name, default) = match arg.kind {
+        ArgKind::Default(value) => (arg.name.to_owned(), fmt!(" [{value}]")),
+        ArgKind::Rest => (fmt!("{}...", arg.name), String::new()),
+        ArgKind::Required | ArgKind::Optional => (arg.name.to_owned(), String::new()),
+    };
+    loop {
+        eprint!("{}", name.bright_cyan().bold());
+        if !choices.is_empty() {
+            eprint!(" {}", format_args!("({numbered})").dim());
+        }
+        eprint!("{}: ", default.dim());
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).unwrap_or_default() == 0 {
+            eprintln!();
+            return None;
+        }
+        let answer = choice_answer(&choices, answer.trim());
+        if answer.is_empty() {
+            if !arg.is_required() {
+                return Some(arg.default_value().into_owned());
+            }
+            continue;
+        }
+        // Validated again with the rest of the arguments
+        match arg.validate(answer.to_owned()) {
+            Ok(_) => return Some(answer.to_owned()),
+            Err(reason) => eprintln!("{}", reason.bright_red()),
+        }
+    }
+}
+
+/// Whether `name` appears whole in `script`, exported or not: 'sha' is not used by '$shasum' or '$my_sha'.
+fn uses_name(script: &str, name: &str) -> bool {
+    script.match_indices(name).any(|(i, _)| {
+        let before = &script[..i];
+        !before.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+            && is_whole_name(name, &script[i + name.len()..])
+    })
+}
+
+/// Replaces the arguments, constants and special variables in `script`.
+///
+/// Each one can be written as `$name` or `${name}`, to separate it from the text that follows.<br>
+/// Arguments and constants written as `$name:q` or `${name:q}` are replaced with a string literal of `lang`.<br>
+/// `$$` is replaced with a literal `$` when it is followed by `{` or by something that would be replaced.
 fn replace_all(
     script: String,
-    args: (&[&str], &[String]),
+    args: &[(&str, Str<'_>)],
     vars: &[(&str, Str<'_>)],
+    lang: Lang,
     runfile_docs: String,
     doc: String,
     usage: String,
 ) -> String {
-    // Replace arguments
-    type Bytes<'a> = beef::lean::Cow<'a, [u8]>;
-
-    let vars_names = vars.iter().map(|(n, _)| Bytes::owned(fmt!("${n}").into()));
-    let vars_values = vars.iter().map(|(_, v)| {
-        let patterns = ["\\n", "\\r", "\\t", "\\0", "\\\"", "\\'", "\\\\", "\\$"];
-        let replace_with = ["\n", "\r", "\t", "\0", "\"", "'", "\\", "$"];
-        let ac = aho_corasick::AhoCorasick::new(patterns).unwrap();
-        ac.replace_all(v, &replace_with).into()
-    });
-
-    let args_names = args
-        .0
+    let mut patterns = Vec::new();
+    let mut replace_with = Vec::new();
+
+    // Arguments go first, so they take precedence over constants with the same name
+    let values = args
         .iter()
-        .map(|n| Bytes::owned(fmt!("${n}").into_bytes()));
-    let args_values = args.1.iter().map(|v| Str::borrowed(v));
+        .map(|(n, v)| (*n, Str::borrowed(v)))
+        .chain(vars.iter().map(|(n, v)| (*n, unescape(v))));
+    for (name, value) in values {
+        let quoted = Str::owned(lang.quote(&value));
+        patterns.extend([
+            fmt!("${name}"),
+            fmt!("${{{name}}}"),
+            fmt!("${name}:q"),
+            fmt!("${{{name}:q}}"),
+        ]);
+        replace_with.extend([value.clone(), value, quoted.clone(), quoted]);
+    }
 
-    let patterns = args_names.chain(vars_names).chain([
-        Bytes::borrowed(b"$doc"),
-        Bytes::borrowed(b"$cmddoc"),
-        Bytes::borrowed(b"$usage"),
-    ]);
+    let special = [
+        ("doc", runfile_docs),
+        ("cmddoc", doc),
+        ("usage", usage),
+        ("run", run_invocation()),
+    ];
+    for (name, value) in special {
+        patterns.extend([fmt!("${name}"), fmt!("${{{name}}}")]);
+        replace_with.extend([Str::owned(value.clone()), Str::owned(value)]);
+    }
 
-    let replace_with = args_values.chain(vars_values).chain([
-        Str::owned(runfile_docs),
-        Str::owned(doc),
-        Str::owned(usage),
-    ]);
+    let dollar = patterns.len();
+    patterns.push("$$".to_owned());
+    replace_with.push(Str::borrowed("$"));
 
     let ac = aho_corasick::AhoCorasick::builder()
         .match_kind(aho_corasick::MatchKind::LeftmostLongest)
-        .build(patterns)
+        .build(&patterns)
         .unwrap();
-    ac.replace_all(&script, &replace_with.collect::<Vec<_>>())
+    let mut replaced = String::with_capacity(script.len());
+    let mut last = 0;
+    for m in ac.find_iter(&script) {
+        let rest = &script[m.start()..];
+        // Falls back to a shorter pattern, '$name:quiet' is '$name' followed by ':quiet'
+        let longest = (0..patterns.len())
+            .filter(|&p| rest.starts_with(&patterns[p]))
+            .filter(|&p| is_whole_name(&patterns[p], &rest[patterns[p].len()..]))
+            // The first one wins, arguments shadow constants
+            .max_by_key(|&p| (patterns[p].len(), std::cmp::Reverse(p)));
+        let Some(pattern) = longest else {
+            continue;
+        };
+        // '$$' only escapes what would be replaced, otherwise it is kept for the shell: 'echo $$'
+        let escapes = |escaped: &str| {
+            escaped.starts_with("${")
+                || patterns[..dollar].iter().any(|p| {
+                    escaped.starts_with(p.as_str()) && is_whole_name(p, &escaped[p.len()..])
+                })
+        };
+        if pattern == dollar && !escapes(&rest[1..]) {
+            continue;
+        }
+        replaced.push_str(&script[last..m.start()]);
+        replaced.push_str(&replace_with[pattern]);
+        last = m.start() + patterns[pattern].len();
+    }
+    replaced.push_str(&script[last..]);
+    replaced
 }
 
 impl PartialEq for Command<'_> {
     fn eq(&self, other: &Self) -> bool {
         self.name == other.name
+            && self.aliases == other.aliases
             && self.doc == other.doc
             && self.lang == other.lang
             && self.args == other.args
+            && self.flags == other.flags
+            && self.deps == other.deps
+            && self.cwd == other.cwd
+            && self.export == other.export
+            && self.conditions == other.conditions
+            && self.hidden == other.hidden
+            && self.confirm == other.confirm
+            && self.prompt == other.prompt
             && self.script_with_indent_fix() == other.script_with_indent_fix()
     }
 }
@@ -236,10 +1213,106 @@ impl std::fmt::Debug for Command<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Command")
             .field("name", &self.name)
+            .field("aliases", &self.aliases)
             .field("doc", &self.doc)
             .field("lang", &self.lang)
             .field("args", &self.args)
+            .field("flags", &self.flags)
+            .field("deps", &self.deps)
+            .field("cwd", &self.cwd)
+            .field("export", &self.export)
+            .field("conditions", &self.conditions)
+            .field("hidden", &self.hidden)
+            .field("confirm", &self.confirm)
+            .field("prompt", &self.prompt)
             .field("script", &self.script_with_indent_fix())
             .finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Arg, ArgKind, Command, Flag};
+    use crate::fmt::Str;
+
+    #[test]
+    fn choice_answer() {
+        let choices = ["dev", "prod"];
+        assert_eq!(super::choice_answer(&choices, "2"), "prod");
+        assert_eq!(super::choice_answer(&choices, "dev"), "dev");
+        assert_eq!(super::choice_answer(&choices, "3"), "3");
+        assert_eq!(super::choice_answer(&["10", "20", "1"], "1"), "1");
+        assert_eq!(super::choice_answer(&["10", "20"], "2"), "20");
+    }
+
+    #[test]
+    fn parse_flags() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let command = |flags| {
+            let arg = Arg::new("x", ArgKind::Required);
+            Command::new(
+                "c",
+                String::new(),
+                crate::lang::Shell.into(),
+                vec![arg],
+                flags,
+                "",
+            )
+        };
+
+        let flagless = command(vec![]);
+        assert_eq!(
+            flagless.parse_flags(&args(&["1", "--foo", "-v"])),
+            Ok((args(&["1", "--foo", "-v"]), vec![]))
+        );
+
+        let flags = command(vec![Flag::new(Some("v"), Some("verbose"), None)]);
+        assert_eq!(
+            flags.parse_flags(&args(&["-v", "-1", "--", "--foo"])),
+            Ok((
+                args(&["-1", "--foo"]),
+                vec![("verbose", Str::borrowed("true"))]
+            ))
+        );
+        assert_eq!(
+            flags.parse_flags(&args(&["--foo"])),
+            Err("Unknown flag '--foo'".to_owned())
+        );
+    }
+
+    #[test]
+    fn uses_name() {
+        assert!(super::uses_name("echo $sha", "sha"));
+        assert!(super::uses_name("echo ${sha}-$shasum", "sha"));
+        assert!(!super::uses_name("echo $shasum $my_sha", "sha"));
+    }
+
+    #[test]
+    fn replace_all() {
+        let replace = |script: &str| {
+            super::replace_all(
+                script.to_owned(),
+                &[("name", Str::borrowed("it's"))],
+                &[
+                    ("name_suffix", Str::borrowed("const")),
+                    ("name", Str::borrowed("shadowed")),
+                ],
+                "sh".parse().unwrap(),
+                String::new(),
+                String::new(),
+                String::new(),
+            )
+        };
+        assert_eq!(replace("$name $name_suffix"), "it's const");
+        assert_eq!(replace("${name}_suffix"), "it's_suffix");
+        assert_eq!(replace("$name:q ${name:q}"), r"'it'\''s' 'it'\''s'");
+        assert_eq!(replace("$$name $${name} $$$$"), "$name ${name} $$$$");
+        assert_eq!(replace("echo $$ $$nam $$$name"), "echo $$ $$nam $$it's");
+        assert_eq!(replace("$nam ${unknown}"), "$nam ${unknown}");
+        assert_eq!(
+            replace("$runner $names $name:quiet"),
+            "$runner $names it's:quiet"
+        );
+        assert!(!replace("$run-x ${run}x").contains("$"));
+    }
+}
@@ -26,6 +26,18 @@ pub enum Error {
     #[error("Expected '(args)' or empty parentheses '()' after command name")]
     PExpectedArgs(Start, End),
 
+    #[error("Required argument '{0}' can't be declared after an optional one")]
+    PRequiredAfterOptional(Name, Start, End),
+
+    #[error("Unknown type '{0}'; expected int, path or choices separated by '|'")]
+    PUnknownArgType(Name, Start, End),
+
+    #[error("Default value of '{0}' is not valid, {1}")]
+    PInvalidDefault(Name, String, Start, End),
+
+    #[error("Rest argument '...{0}' must be the last one")]
+    PRestNotLast(Name, Start, End),
+
     #[error("Expected open parentheses '('")]
     PExpectedOpenParen(Start, End),
 
@@ -44,8 +56,44 @@ pub enum Error {
     #[error("Failed to parse included file '{1}': {0}")]
     PIncludeParse(String, Name, Start, End),
 
-    #[error("Failed to parse math expression")]
-    PMathExpression(Start, End),
+    #[error("Failed to read dotenv file '{1}': {0}")]
+    PDotenvRead(String, Name, Start, End),
+
+    #[error("Unknown attribute '{0}'; expected one of [cwd, export, hidden, confirm, prompt, os, arch, which, if]")]
+    PUnknownAttribute(Name, Start, End),
+
+    #[error("Invalid attribute '{0}', {1}")]
+    PInvalidAttribute(Name, String, Start, End),
+
+    #[error("Attribute '{0}' is written more than once")]
+    PDuplicateAttribute(Name, Start, End),
+
+    #[error("'{0}' is already the name or alias of another {1}")]
+    PDuplicateName(Name, &'static str, Start, End),
+
+    #[error("Command '{0}' does not exist")]
+    PUnknownDependency(Name, Start, End),
+
+    #[error("Dependency '{0}' can't have required arguments")]
+    PDependencyArgs(Name, Start, End),
+
+    #[error("Dependency cycle: {0}")]
+    PDependencyCycle(String, Start, End),
+
+    #[error("Failed to calculate math expression: {0}")]
+    PMathExpression(String, Start, End),
+
+    #[error("Undefined constant '{0}', constants must be declared before they are used")]
+    PUndefinedConstant(Name, Start, End),
+
+    #[error(
+        "Constant '{0}' is evaluated when a command uses it, it can't be used in other constants"
+    )]
+    PShellConstant(Name, Start, End),
+
+    /// Error in an included file, with its name and contents.
+    #[error("{0}")]
+    PIncluded(Box<Error>, Name, String),
 
     #[error("{0}")]
     Custom(Str<'static>, Start, End),
@@ -63,6 +111,9 @@ impl Error {
         input: impl AsRef<str>,
         color: ariadne::Color,
     ) -> std::io::Result<()> {
+        if let Error::PIncluded(e, file, input) = self {
+            return e.ariadne(file, input, color);
+        }
         let msg = self.to_string();
         let (start, end) = self.span();
 
@@ -99,14 +150,29 @@ impl Error {
             Error::PExpectedLangOrCmd(start, end) => (*start, *end),
             Error::PExpectedCmdName(start, end) => (*start, *end),
             Error::PExpectedArgs(start, end) => (*start, *end),
+            Error::PRequiredAfterOptional(_, start, end) => (*start, *end),
+            Error::PUnknownArgType(_, start, end) => (*start, *end),
+            Error::PInvalidDefault(_, _, start, end) => (*start, *end),
+            Error::PRestNotLast(_, start, end) => (*start, *end),
             Error::PExpectedOpenParen(start, end) => (*start, *end),
             Error::PExpectedCloseParen(start, end) => (*start, *end),
             Error::PExpectedBodyStart(start, end) => (*start, *end),
             Error::PExpectedBodyEnd(_, start, end) => (*start, *end),
             Error::PIncludeRead(_, _, start, end) => (*start, *end),
             Error::PIncludeParse(_, _, start, end) => (*start, *end),
-            Error::PMathExpression(start, end) => (*start, *end),
+            Error::PDotenvRead(_, _, start, end) => (*start, *end),
+            Error::PUnknownAttribute(_, start, end) => (*start, *end),
+            Error::PInvalidAttribute(_, _, start, end) => (*start, *end),
+            Error::PDuplicateAttribute(_, start, end) => (*start, *end),
+            Error::PDuplicateName(_, _, start, end) => (*start, *end),
+            Error::PUnknownDependency(_, start, end) => (*start, *end),
+            Error::PDependencyArgs(_, start, end) => (*start, *end),
+            Error::PDependencyCycle(_, start, end) => (*start, *end),
+            Error::PMathExpression(_, start, end) => (*start, *end),
+            Error::PUndefinedConstant(_, start, end) => (*start, *end),
+            Error::PShellConstant(_, start, end) => (*start, *end),
             Error::PParseLang(_, start, end) => (*start, *end),
+            Error::PIncluded(e, _, _) => e.span(),
             Error::Custom(_, start, end) => (*start, *end),
             Error::Unknown => (0, 0),
         }
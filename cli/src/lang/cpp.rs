@@ -26,15 +26,29 @@ impl super::Language for Cpp {
         super::program_with_alternatives(BINARIES, self.nix_packages())
     }
 
-    fn execute(&self, input: &str, args: impl AsRef<[String]>) -> Result<(), Str<'_>> {
-        super::execute_compiled(
+    fn quote(&self, s: &str) -> String {
+        super::c_quote(s)
+    }
+
+    /// An initializer list, `{}` without arguments, so it needs a type that can be empty, like 'std::vector<std::string>'.
+    fn list(&self, items: &[String]) -> String {
+        let items = items.iter().map(|i| self.quote(i)).collect::<Vec<_>>();
+        format!("{{{}}}", items.join(", "))
+    }
+
+    fn prepare(
+        &self,
+        input: &str,
+        args: impl AsRef<[String]>,
+    ) -> Result<std::process::Command, Str<'_>> {
+        super::prepare_compiled(
             "cpp",
             "main.cpp",
             input,
             args,
             None,
             self.program()?.args(["main.cpp", "-o", "main"]),
-            &mut std::process::Command::new("./main"),
+            |path| std::process::Command::new(path.join("main")),
         )
     }
 }
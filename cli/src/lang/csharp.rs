@@ -14,12 +14,22 @@ impl super::Language for CSharp {
         &["dotnet-sdk"]
     }
 
-    fn execute(
+    fn quote(&self, s: &str) -> String {
+        // '\x' takes up to four hex digits, '\u' always four
+        super::escaped_quote(s, |c| format!("\\u{c:04x}"))
+    }
+
+    fn list(&self, items: &[String]) -> String {
+        let items = items.iter().map(|i| self.quote(i)).collect::<Vec<_>>();
+        format!("new string[] {{{}}}", items.join(", "))
+    }
+
+    fn prepare(
         &self,
         input: &str,
         args: impl AsRef<[String]>,
-    ) -> Result<(), crate::fmt::Str<'static>> {
-        super::execute_compiled(
+    ) -> Result<std::process::Command, crate::fmt::Str<'static>> {
+        super::prepare_compiled(
             "csharp",
             "Program.cs",
             input,
@@ -28,8 +38,9 @@ impl super::Language for CSharp {
                 self.program()?
                     .args(["new", "console", "-n", "runfile", "-o", "."]),
             ),
-            self.program()?.arg("build"),
-            self.program()?.args(["run", "--no-build"]),
+            self.program()?.args(["build", "-o", "out"]),
+            // Not 'dotnet run', it would read the 'global.json' of the directory 'run' is called from
+            |path| std::process::Command::new(path.join("out/runfile")),
         )
     }
 }
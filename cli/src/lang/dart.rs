@@ -13,4 +13,9 @@ impl super::Language for Dart {
     fn nix_packages(&self) -> &'static [&'static str] {
         &[]
     }
+
+    fn quote(&self, s: &str) -> String {
+        // Dart interpolates '$' inside strings
+        super::double_quote(s).replace('$', "\\$")
+    }
 }
\ No newline at end of file
@@ -41,12 +41,31 @@ pub trait Language {
     fn as_str(&self) -> &'static str;
     fn binary(&self) -> &'static str;
     fn nix_packages(&self) -> &'static [&'static str];
-    fn execute(&self, input: &str, args: impl AsRef<[String]>) -> Result<(), Str<'_>> {
-        execute_interpreted(self.program()?, input, args)
+    /// Prepares the process that runs `input` with `args`, compiling it if needed.
+    fn prepare(
+        &self,
+        input: &str,
+        args: impl AsRef<[String]>,
+    ) -> Result<std::process::Command, Str<'_>> {
+        prepare_interpreted(self.program()?, input, args)
+    }
+    /// Runs `input` with `args` until it exits, returning its exit status.
+    fn execute(&self, input: &str, args: impl AsRef<[String]>) -> Result<std::process::ExitStatus, Str<'_>> {
+        let mut program = self.prepare(input, args)?;
+        program.status().map_err(|error| execution_failed(program.get_program().to_string_lossy(), error))
     }
     fn installed(&self) -> bool {
         which::which(self.binary()).is_ok()
     }
+    /// Returns `s` as a string literal of the language.
+    fn quote(&self, s: &str) -> String {
+        double_quote(s)
+    }
+    /// Returns `items` as a list literal of the language, used to expand rest arguments.
+    fn list(&self, items: &[String]) -> String {
+        let items = items.iter().map(|i| self.quote(i)).collect::<Vec<_>>();
+        format!("[{}]", items.join(", "))
+    }
     fn program(&self) -> Result<std::process::Command, Str<'static>> {
         which::which(self.binary())
             .map(std::process::Command::new)
@@ -69,6 +88,42 @@ fn exe_not_found(exe: impl AsRef<str>, error: which::Error) -> Str<'static> {
     Str::from(error)
 }
 
+/// Quotes `s` as a double quoted string with C-like escapes, valid in most languages.
+fn double_quote(s: &str) -> String {
+    escaped_quote(s, |c| format!("\\x{c:02x}"))
+}
+
+/// Quotes `s` as a C string literal.
+///
+/// Hexadecimal escapes would consume the hex digits that follow them, octal ones have a fixed length.
+fn c_quote(s: &str) -> String {
+    escaped_quote(s, |c| format!("\\{c:03o}"))
+}
+
+/// Quotes `s` with the common escapes, and other control characters escaped by `control`.
+fn escaped_quote(s: &str, control: impl Fn(u8) -> String) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_ascii_control() => quoted.push_str(&control(c as u8)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quotes `s` as a single word for POSIX shells.
+pub fn single_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
 fn installed_any(binaries: impl AsRef<[&'static str]>) -> bool {
     binaries.as_ref().iter().any(|&binary| which::which(binary).is_ok())
 }
@@ -77,7 +132,7 @@ fn installed_all(binaries: impl AsRef<[&'static str]>) -> bool {
     binaries.as_ref().iter().all(|&binary| which::which(binary).is_ok())
 }
 
-fn execution_failed(
+pub fn execution_failed(
     exe: impl std::fmt::Display,
     error: impl std::fmt::Display,
 ) -> Str<'static> {
@@ -100,13 +155,17 @@ fn write_to_tmp(dir: &str, input: &str) -> Result<std::path::PathBuf, Str<'stati
     Ok(file)
 }
 
-fn wait_for_child(mut child: std::process::Child) -> Result<(), Str<'static>> {
+/// Runs the prepared process until it exits.
+pub fn spawn_and_wait(mut program: std::process::Command) -> Result<(), Str<'static>> {
+    let child = program
+        .spawn()
+        .map_err(|error| execution_failed(program.get_program().to_string_lossy(), error))?;
+    wait_for_child(child)
+}
+
+pub fn wait_for_child(mut child: std::process::Child) -> Result<(), Str<'static>> {
     match child.wait() {
-        Ok(status) if status.success() => Ok(()),
-        Ok(status) => Err(Str::from(format!(
-            "Command exited with status code {}",
-            status.code().unwrap_or(-1)
-        ))),
+        Ok(status) => exit_status(status),
         Err(e) => Err(Str::from(format!(
             "Failed to wait for command to exit: {}",
             e
@@ -114,6 +173,17 @@ fn wait_for_child(mut child: std::process::Child) -> Result<(), Str<'static>> {
     }
 }
 
+/// Converts an unsuccessful exit status into an error.
+pub fn exit_status(status: std::process::ExitStatus) -> Result<(), Str<'static>> {
+    if status.success() {
+        return Ok(());
+    }
+    Err(Str::from(format!(
+        "Command exited with status code {}",
+        status.code().unwrap_or(-1)
+    )))
+}
+
 /// Creates a `std::process::Command` for the first program found in the PATH or in the Nix shell.
 fn program_with_alternatives(programs: &[&'static str], nix_packages: &[&'static str]) -> Result<std::process::Command, Str<'static>> {
     programs
@@ -127,28 +197,24 @@ fn program_with_alternatives(programs: &[&'static str], nix_packages: &[&'static
     .or_else(|error| crate::nix::nix_shell(nix_packages, programs[0]).ok_or(error))
 }
 
-/// Runs the given program with only one argument consisting in a file containing the input.
+/// Prepares the given program with only one argument consisting in a file containing the input.
 ///
 /// ```
-/// execute_simple("python", "print('Hello')");
+/// prepare_interpreted("python", "print('Hello')");
 /// ```
 /// Is equivalent to
 /// ```bash
-/// echo "print('Hello')" > /tmp/run/input && python /tmp/run/input
+/// echo "print('Hello')" > /tmp/run/<hash>/input && python /tmp/run/<hash>/input
 /// ```
-fn execute_interpreted(
+fn prepare_interpreted(
     mut program: std::process::Command,
     input: &str,
     args: impl AsRef<[String]>,
-) -> Result<(), Str<'static>> {
-    let name = format!("{:?}", program.get_program());
-    let file = write_to_tmp("input", input).unwrap();
-    let child = program
-        .arg(file)
-        .args(args.as_ref())
-        .spawn()
-        .map_err(|error| execution_failed(name, error))?;
-    wait_for_child(child)
+) -> Result<std::process::Command, Str<'static>> {
+    // Each script gets its own file, so commands running in parallel don't overwrite each other
+    let file = write_to_tmp(&format!("{:x}", md5::compute(input)), input)?;
+    program.arg(file).args(args.as_ref());
+    Ok(program)
 }
 
 /// Creates a project directory and writes the input to the main file.
@@ -187,9 +253,12 @@ fn create_project(
     Ok(path)
 }
 
-/// Creates the project directory, compiles and runs the specified input.
+/// Creates the project directory, compiles the specified input and prepares the process that runs it.
+///
+/// The compilation happens in the project directory, `run` receives its absolute path
+/// so the produced program can be spawned from any working directory.
 ///
-/// Use in the implementation of `Language::execute`.
+/// Use in the implementation of `Language::prepare`.
 /// # Example
 /// ```rust
 /// let lang = "rust";
@@ -200,23 +269,26 @@ fn create_project(
 ///     self.program()?.args(["init", "--name", "runfile"])
 /// );
 /// let compile = self.program()?.args(["build", "--color", "always"]);
-/// let run = self.program()?.args(["run", "-q", "--"]);
-/// execute_compiled(lang, proj_main, input, args, init, compile, run)
+/// let mut run = self.program()?;
+/// let run = |path: &std::path::Path| {
+///     run.arg("run").arg("--manifest-path").arg(path.join("Cargo.toml"));
+///     run
+/// };
+/// prepare_compiled(lang, proj_main, input, args, init, compile, run)
 /// ```
-fn execute_compiled(
+fn prepare_compiled(
     lang: &str,
     proj_main: impl AsRef<std::path::Path>,
     input: &str,
     args: impl AsRef<[String]>,
     init: Option<&mut std::process::Command>,
     compile: &mut std::process::Command,
-    run: &mut std::process::Command,
-) -> Result<(), Str<'static>> {
+    run: impl FnOnce(&std::path::Path) -> std::process::Command,
+) -> Result<std::process::Command, Str<'static>> {
     let path = create_project(lang, init, proj_main, input)?;
-    std::env::set_current_dir(&path)
-        .map_err(|e| format!("Could not set current directory to {path:?}\nComplete error: {e}"))?;
 
     let compile = compile
+        .current_dir(&path)
         .output()
         .map_err(|error| execution_failed(compile.get_program().to_string_lossy(), error))?;
 
@@ -226,12 +298,9 @@ fn execute_compiled(
         return Err(Str::from(err));
     }
 
-    let child = run
-        .args(args.as_ref())
-        .spawn()
-        .map_err(|error| execution_failed(run.get_program().to_string_lossy(), error))?;
-
-    wait_for_child(child)
+    let mut run = run(&path);
+    run.args(args.as_ref());
+    Ok(run)
 }
 
 impl std::str::FromStr for Lang {
@@ -269,3 +338,33 @@ impl Default for Lang {
         Shell.into()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Lang, Language as _};
+
+    #[test]
+    fn list() {
+        let items = ["a b".to_owned(), "it's \"quoted\"".to_owned()];
+        let lang = |l: &str| l.parse::<Lang>().unwrap();
+        assert_eq!(lang("sh").list(&items), r#"'a b' 'it'\''s "quoted"'"#);
+        assert_eq!(lang("py").list(&items), r#"["a b", "it's \"quoted\""]"#);
+        assert_eq!(lang("rs").list(&items), r#"["a b", "it's \"quoted\""]"#);
+        assert_eq!(lang("c").list(&items), r#"{"a b", "it's \"quoted\""}"#);
+        assert_eq!(lang("sh").list(&[]), "");
+        assert_eq!(lang("rs").list(&[]), r#"[""; 0]"#);
+        assert_eq!(lang("c").list(&[]), "{}");
+    }
+
+    #[test]
+    fn quote() {
+        let lang = |l: &str| l.parse::<Lang>().unwrap();
+        let s = "it's \"$x\"\n\x01a";
+        assert_eq!(lang("sh").quote(s), "'it'\\''s \"$x\"\n\x01a'");
+        assert_eq!(lang("py").quote(s), r#""it's \"$x\"\n\x01a""#);
+        assert_eq!(lang("rs").quote(s), r#""it's \"$x\"\n\u{1}a""#);
+        assert_eq!(lang("c").quote(s), r#""it's \"$x\"\n\001a""#);
+        assert_eq!(lang("cs").quote(s), r#""it's \"$x\"\n\u0001a""#);
+        assert_eq!(lang("dart").quote(s), r#""it's \"\$x\"\n\x01a""#);
+    }
+}
@@ -22,16 +22,37 @@ impl super::Language for Rust {
         super::installed_all([BINARY, "rustc"])
     }
 
-    fn execute(&self, input: &str, args: impl AsRef<[String]>) -> Result<(), Str<'_>> {
+    fn quote(&self, s: &str) -> String {
+        // Debug output of a string is a valid Rust string literal
+        format!("{s:?}")
+    }
+
+    fn list(&self, items: &[String]) -> String {
+        // '[]' alone has no type, it can't be used without annotations
+        if items.is_empty() {
+            return r#"[""; 0]"#.to_owned();
+        }
+        let items = items.iter().map(|i| self.quote(i)).collect::<Vec<_>>();
+        format!("[{}]", items.join(", "))
+    }
+
+    fn prepare(
+        &self,
+        input: &str,
+        args: impl AsRef<[String]>,
+    ) -> Result<std::process::Command, Str<'_>> {
         let input = format!("fn main() {{\n{}\n}}", input);
-        super::execute_compiled(
+        super::prepare_compiled(
             "rust",
             "src/main.rs",
             &input,
             args,
             Some(self.program()?.args(["init", "--name", "runfile"])),
-            self.program()?.args(["build", "--color", "always"]),
-            self.program()?.args(["run", "-q", "--"]),
+            // The target directory is fixed, the environment could move it outside of the project
+            self.program()?
+                .args(["build", "--color", "always", "--target-dir", "target"]),
+            // Not 'cargo run', it would read the '.cargo/config.toml' of the directory 'run' is called from
+            |path| std::process::Command::new(path.join("target/debug/runfile")),
         )
     }
 }
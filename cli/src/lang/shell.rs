@@ -13,4 +13,13 @@ impl super::Language for Shell {
     fn nix_packages(&self) -> &'static [&'static str] {
         &[]
     }
+
+    fn quote(&self, s: &str) -> String {
+        super::single_quote(s)
+    }
+
+    fn list(&self, items: &[String]) -> String {
+        let items = items.iter().map(|i| self.quote(i)).collect::<Vec<_>>();
+        items.join(" ")
+    }
 }
@@ -12,8 +12,10 @@ mod command;
 mod error;
 mod fmt;
 mod lang;
+mod math;
 mod nix;
 mod parser;
+mod picker;
 mod runfile;
 mod utils;
 
@@ -30,28 +32,26 @@ fn main() -> std::io::Result<()> {
     }
 
     if args.first().is_some_and_oneof(["--print-complete"]) {
-        crate::clap::print_completion();
+        // Complete the commands of the runfile in the current directory, if any
+        let found = find_runfile();
+        let runfile = found
+            .as_ref()
+            .and_then(|(file, input)| parser::runfile(input, runfile_dir(file), false).ok())
+            .and_then(Result::ok);
+        crate::clap::print_completion(runfile.as_ref());
         return Ok(());
     }
 
+    if args.first().is_some_and_oneof(["--run"]) {
+        run_lang(&args[1..]);
+    }
+
     let (file, input) = get_file(&mut args);
-    let dot = std::path::Path::new(".");
-    let path = if file == "stdin" {
-        dot
-    } else {
-        std::path::Path::new(file.as_ref())
-            .parent()
-            .map(|p| {
-                if p == std::path::Path::new("") {
-                    dot
-                } else {
-                    p
-                }
-            })
-            .unwrap_or(dot)
-    };
+    // Absolute, commands run in this directory wherever `run` is called from
+    let path = runfile_dir(&file);
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
 
-    let runfile = match parser::runfile(&input, path) {
+    let runfile = match parser::runfile(&input, &path, false) {
         Ok(r) => match r {
             Ok(r) => r,
             Err(errors) => {
@@ -73,7 +73,15 @@ fn main() -> std::io::Result<()> {
         }
     };
 
-    runfile.run((" ", [get_current_exe()?]), &args).unwrap();
+    if let Err(errors) = runfile.check_dependencies() {
+        print_errors(errors, file, &input)?;
+        std::process::exit(1);
+    }
+
+    set_run_env(&file)?;
+    runfile
+        .run((" ", [get_current_exe()?]), &args, Default::default())
+        .unwrap();
 
     Ok(())
 }
@@ -106,15 +114,39 @@ fn print_help() {
         "--file".bright_cyan().bold(),
         "<FILE>".cyan()
     );
+    println!(
+        "  {}, {} {}\tRuns up to N dependencies of the command at the same time",
+        "-j".bright_cyan().bold(),
+        "--jobs".bright_cyan().bold(),
+        "<N>".cyan()
+    );
+    println!(
+        "  {}, {}\t\tAnswers yes to the confirmations of the commands, like 'RUN_YES=1'",
+        "-y".bright_cyan().bold(),
+        "--yes".bright_cyan().bold()
+    );
+    println!(
+        "      {}\t\tPicks the command to run from a list filtered as you type",
+        "--pick".bright_cyan().bold()
+    );
+    println!(
+        "      {}\t\tAsks for the missing arguments of the command, like 'RUN_PROMPT=1'",
+        "--prompt".bright_cyan().bold()
+    );
     println!(
         "  {}, {}\tPrints available commands in the runfile or subcommand",
         "-c".bright_cyan().bold(),
         "--commands".bright_cyan().bold()
     );
     println!(
-        "      {}\tPrints the completion script for the current shell",
+        "      {}\tPrints the completion script for the current shell and runfile",
         "--print-complete".bright_cyan().bold()
     );
+    println!(
+        "      {} {}\tRuns FILE or stdin as the specified language, instead of a runfile\n\t\t\tIt must be the FIRST argument",
+        "--run".bright_cyan().bold(),
+        "<LANG>".cyan()
+    );
     println!(
         "      {}\t\tEnables reading the runfile from stdin",
         "--stdin".bright_cyan().bold()
@@ -143,6 +175,88 @@ fn print_errors(
     Ok(())
 }
 
+/// Runs a file or stdin as the given language.
+///
+/// Called as `run --run <LANG> [FILE|-] [ARGS...]`, which also allows using `run` in a shebang.
+fn run_lang(args: &[String]) -> ! {
+    let usage = || {
+        eprintln!(
+            "{} {} {}",
+            "Usage:".bright_green().bold(),
+            "run --run <LANG>".bright_cyan().bold(),
+            "[FILE|-] [ARGS...]".cyan()
+        );
+        std::process::exit(1);
+    };
+    let Some(lang) = args.first() else {
+        eprintln!("{}", "Error: No language specified".bright_red().bold());
+        usage()
+    };
+    let lang = match lang.parse::<lang::Lang>() {
+        Ok(lang) => lang,
+        Err(lang) => {
+            eprintln!(
+                "{}Error: Unknown language '{lang}'{}",
+                "".bright_red().bold().linger(),
+                "".clear()
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let input = match args.get(1).map(String::as_str) {
+        Some("-") | None => read_pipe::read_pipe(),
+        Some(file) => std::fs::read_to_string(file).ok(),
+    };
+    let Some(input) = input else {
+        match args.get(1) {
+            Some(file) if file != "-" => eprintln!(
+                "{}Error: Could not read file '{file}'{}",
+                "".bright_red().bold().linger(),
+                "".clear()
+            ),
+            _ => eprintln!("{}", "Error: Nothing to run in stdin".bright_red().bold()),
+        }
+        usage()
+    };
+    let args = args.get(2..).unwrap_or_default();
+
+    // The shebang is not valid in most languages, keep the line to preserve error locations
+    let input = match input.strip_prefix("#!") {
+        Some(rest) if !rest.starts_with('[') => {
+            rest.find('\n').map(|n| &rest[n..]).unwrap_or_default()
+        }
+        _ => &input,
+    };
+
+    // The script's own status code is kept, for the programs calling it
+    match lang::Language::execute(&lang, input, args) {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Returns the directory containing the runfile, used to resolve includes.
+fn runfile_dir(file: &str) -> &std::path::Path {
+    let dot = std::path::Path::new(".");
+    if file == "stdin" {
+        return dot;
+    }
+    std::path::Path::new(file)
+        .parent()
+        .map(|p| {
+            if p == std::path::Path::new("") {
+                dot
+            } else {
+                p
+            }
+        })
+        .unwrap_or(dot)
+}
+
 fn get_file(args: &mut Vec<String>) -> (Str<'static>, String) {
     let first = args.first();
 
@@ -180,6 +294,27 @@ fn get_file(args: &mut Vec<String>) -> (Str<'static>, String) {
         std::process::exit(1);
     }
 
+    if let Some(found) = find_runfile() {
+        return found;
+    }
+
+    eprintln!("{}", "Error: Could not find runfile".bold().bright_red());
+    let style = yansi::Style::new().bright_magenta().bold();
+    eprintln!(
+        "Possible file names: [{}, {}] or any ending in {}",
+        "run".paint(style),
+        "runfile".paint(style),
+        ".run".paint(style)
+    );
+    eprintln!(
+        "See '{}' for more information",
+        "run --help".bright_cyan().bold()
+    );
+    std::process::exit(1);
+}
+
+/// Searches the current directory for a runfile, returning its name and contents.
+fn find_runfile() -> Option<(Str<'static>, String)> {
     let files = [
         "runfile",
         "run",
@@ -192,21 +327,11 @@ fn get_file(args: &mut Vec<String>) -> (Str<'static>, String) {
     ];
     for file in files {
         if let Ok(contents) = std::fs::read_to_string(file) {
-            return (file.into(), contents);
+            return Some((file.into(), contents));
         }
     }
 
-    let files = match std::fs::read_dir(".") {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!(
-                "{}Error: {e}{}",
-                "".bright_red().bold().linger(),
-                "".clear()
-            );
-            std::process::exit(1);
-        }
-    };
+    let files = std::fs::read_dir(".").ok()?;
 
     for file in files.flatten() {
         let path = file.path();
@@ -215,23 +340,24 @@ fn get_file(args: &mut Vec<String>) -> (Str<'static>, String) {
             let contents = std::fs::read_to_string(path);
 
             if let (Some(name), Ok(contents)) = (name, contents) {
-                return (name.into(), contents);
+                return Some((name.into(), contents));
             }
         }
     }
-    eprintln!("{}", "Error: Could not find runfile".bold().bright_red());
-    let style = yansi::Style::new().bright_magenta().bold();
-    eprintln!(
-        "Possible file names: [{}, {}] or any ending in {}",
-        "run".paint(style),
-        "runfile".paint(style),
-        ".run".paint(style)
-    );
-    eprintln!(
-        "See '{}' for more information",
-        "run --help".bright_cyan().bold()
-    );
-    std::process::exit(1);
+    None
+}
+
+/// Exports the absolute paths of `run` and the runfile, so commands can call other commands of the same runfile.
+///
+/// Inherited by every command, `$run` is built from them.
+fn set_run_env(file: &str) -> std::io::Result<()> {
+    std::env::set_var("RUN_EXE", std::env::current_exe()?);
+    // A runfile read from stdin can't be found again, don't inherit the one of a parent `run`
+    match std::fs::canonicalize(file) {
+        Ok(path) if file != "stdin" => std::env::set_var("RUNFILE", path),
+        _ => std::env::remove_var("RUNFILE"),
+    }
+    Ok(())
 }
 
 fn get_current_exe() -> std::io::Result<String> {
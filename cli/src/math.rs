@@ -0,0 +1,254 @@
+//! Arithmetic of the `$(...)` constants.
+
+/// Result of a math expression, integers are only converted to floats when mixed with them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn float(self) -> f64 {
+        match self {
+            Number::Int(i) => i as f64,
+            Number::Float(f) => f,
+        }
+    }
+
+    fn int(self, op: &str) -> Result<i64, String> {
+        match self {
+            Number::Int(i) => Ok(i),
+            Number::Float(_) => Err(format!("'{op}' can only be used with integers")),
+        }
+    }
+
+    fn is_true(self) -> bool {
+        self.float() != 0.0
+    }
+
+    /// Converts a float without decimals to an integer.
+    fn to_int(f: f64, op: &str) -> Result<Number, String> {
+        if f.is_finite() && f >= i64::MIN as f64 && f <= i64::MAX as f64 {
+            Ok(Number::Int(f as i64))
+        } else {
+            Err(format!("The result of '{op}' does not fit in an integer"))
+        }
+    }
+}
+
+impl std::fmt::Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Number::Int(i) => write!(f, "{i}"),
+            Number::Float(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+enum Expr<'i> {
+    Number(Number),
+    Unary(&'static str, Box<Expr<'i>>),
+    Binary(&'static str, Box<Expr<'i>>, Box<Expr<'i>>),
+    Ternary(Box<Expr<'i>>, Box<Expr<'i>>, Box<Expr<'i>>),
+    Call(&'i str, Vec<Expr<'i>>),
+}
+
+fn unary<'i>(op: &'static str, v: Expr<'i>) -> Expr<'i> {
+    Expr::Unary(op, Box::new(v))
+}
+
+fn binary<'i>(op: &'static str, x: Expr<'i>, y: Expr<'i>) -> Expr<'i> {
+    Expr::Binary(op, Box::new(x), Box::new(y))
+}
+
+peg::parser!( grammar arithmetic() for str {
+    rule _ = [' ' | '\t' | '\n' | '\r']*
+    pub(crate) rule expression() -> Expr<'input> = _ e:ternary() _ { e }
+    rule ternary() -> Expr<'input> = c:binary() _ "?" _ a:ternary() _ ":" _ b:ternary() {
+        Expr::Ternary(Box::new(c), Box::new(a), Box::new(b))
+    } / binary()
+    rule binary() -> Expr<'input> = precedence!{
+        x:(@) _ "|" !"|" _ y:@ { binary("|", x, y) }
+        --
+        x:(@) _ "&" !"&" _ y:@ { binary("&", x, y) }
+        --
+        x:(@) _ "==" _ y:@ { binary("==", x, y) }
+        x:(@) _ "!=" _ y:@ { binary("!=", x, y) }
+        --
+        x:(@) _ "<=" _ y:@ { binary("<=", x, y) }
+        x:(@) _ ">=" _ y:@ { binary(">=", x, y) }
+        x:(@) _ "<" !"<" _ y:@ { binary("<", x, y) }
+        x:(@) _ ">" !">" _ y:@ { binary(">", x, y) }
+        --
+        x:(@) _ "<<" _ y:@ { binary("<<", x, y) }
+        x:(@) _ ">>" _ y:@ { binary(">>", x, y) }
+        --
+        x:(@) _ "+" _ y:@ { binary("+", x, y) }
+        x:(@) _ "-" _ y:@ { binary("-", x, y) }
+        --
+        x:(@) _ "*" _ y:@ { binary("*", x, y) }
+        x:(@) _ "/" _ y:@ { binary("/", x, y) }
+        x:(@) _ "%" _ y:@ { binary("%", x, y) }
+        --
+              "-" _ v:@ { unary("-", v) }
+              "~" _ v:@ { unary("~", v) }
+        --
+        x:@ _ "^" _ y:(@) { binary("^", x, y) }
+        --
+        v:@ _ "!" !"=" { unary("!", v) }
+        --
+        "(" _ e:ternary() _ ")" { e }
+        f:$(['a'..='z']+) _ "(" _ args:(ternary() ** (_ "," _)) _ ")" { Expr::Call(f, args) }
+        n:number() { Expr::Number(n) }
+    }
+
+    rule number() -> Number
+        = "0x" n:$(['0'..='9' | 'a'..='f' | 'A'..='F']+) {? i64::from_str_radix(n, 16).map(Number::Int).or(Err("64 bit integer")) }
+        / "0b" n:$(['0' | '1']+) {? i64::from_str_radix(n, 2).map(Number::Int).or(Err("64 bit integer")) }
+        / n:$(['0'..='9']+ "." ['0'..='9']* / "." ['0'..='9']+) {? n.parse().map(Number::Float).or(Err("number")) }
+        / n:$(['0'..='9']+) {? n.parse().map(Number::Int).or(Err("64 bit integer")) }
+});
+
+/// Calculates `expression`, returning the reason it failed otherwise.
+pub fn calculate(expression: &str) -> Result<Number, String> {
+    let expr = arithmetic::expression(expression).map_err(|e| {
+        format!(
+            "Expected {} at position {}",
+            e.expected,
+            e.location.offset + 1
+        )
+    })?;
+    let result = eval(&expr)?;
+    match result {
+        Number::Float(f) if !f.is_finite() => Err("The result is not a finite number".into()),
+        result => Ok(result),
+    }
+}
+
+fn eval(expr: &Expr) -> Result<Number, String> {
+    use Number::{Float, Int};
+
+    let overflow =
+        || "Integer overflow, use a decimal number like '1.0' to calculate with floats".to_owned();
+    match expr {
+        Expr::Number(n) => Ok(*n),
+        Expr::Ternary(c, a, b) => {
+            if eval(c)?.is_true() {
+                eval(a)
+            } else {
+                eval(b)
+            }
+        }
+        Expr::Unary(op, v) => match (*op, eval(v)?) {
+            ("-", Int(i)) => i.checked_neg().map(Int).ok_or_else(overflow),
+            ("-", Float(f)) => Ok(Float(-f)),
+            ("~", v) => Ok(Int(!v.int("~")?)),
+            ("!", Int(i)) if i < 0 => Err("Factorial of a negative number".into()),
+            ("!", Int(i)) => (1..=i)
+                .try_fold(1i64, |acc, n| acc.checked_mul(n))
+                .map(Int)
+                .ok_or_else(overflow),
+            ("!", Float(_)) => Err("Factorial of a decimal number".into()),
+            _ => unreachable!("unknown unary operator {op}"),
+        },
+        Expr::Binary(op, x, y) => {
+            let (x, y) = (eval(x)?, eval(y)?);
+            let bool = |b: bool| Ok(Int(b as i64));
+            match (*op, x, y) {
+                ("/" | "%", _, y) if y.float() == 0.0 => Err("Division by zero".into()),
+                ("+", Int(x), Int(y)) => x.checked_add(y).map(Int).ok_or_else(overflow),
+                ("-", Int(x), Int(y)) => x.checked_sub(y).map(Int).ok_or_else(overflow),
+                ("*", Int(x), Int(y)) => x.checked_mul(y).map(Int).ok_or_else(overflow),
+                ("/", Int(x), Int(y)) => x.checked_div(y).map(Int).ok_or_else(overflow),
+                ("%", Int(x), Int(y)) => x.checked_rem(y).map(Int).ok_or_else(overflow),
+                ("^", Int(x), Int(y)) if y >= 0 => u32::try_from(y)
+                    .ok()
+                    .and_then(|y| x.checked_pow(y))
+                    .map(Int)
+                    .ok_or_else(overflow),
+                ("+", x, y) => Ok(Float(x.float() + y.float())),
+                ("-", x, y) => Ok(Float(x.float() - y.float())),
+                ("*", x, y) => Ok(Float(x.float() * y.float())),
+                ("/", x, y) => Ok(Float(x.float() / y.float())),
+                ("%", x, y) => Ok(Float(x.float() % y.float())),
+                ("^", x, y) => Ok(Float(x.float().powf(y.float()))),
+                ("&", x, y) => Ok(Int(x.int("&")? & y.int("&")?)),
+                ("|", x, y) => Ok(Int(x.int("|")? | y.int("|")?)),
+                ("<<" | ">>", x, y) => {
+                    let (x, y) = (x.int(op)?, y.int(op)?);
+                    let y = u32::try_from(y).map_err(|_| format!("Negative shift in '{op}'"))?;
+                    let shifted = if *op == "<<" {
+                        x.checked_shl(y)
+                    } else {
+                        x.checked_shr(y)
+                    };
+                    shifted.map(Int).ok_or_else(overflow)
+                }
+                ("==", x, y) => bool(x.float() == y.float()),
+                ("!=", x, y) => bool(x.float() != y.float()),
+                ("<", x, y) => bool(x.float() < y.float()),
+                ("<=", x, y) => bool(x.float() <= y.float()),
+                (">", x, y) => bool(x.float() > y.float()),
+                (">=", x, y) => bool(x.float() >= y.float()),
+                _ => unreachable!("unknown binary operator {op}"),
+            }
+        }
+        Expr::Call(f, args) => {
+            let args = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+            let one = || match args.as_slice() {
+                [v] => Ok(*v),
+                _ => Err(format!("'{f}' expects one argument")),
+            };
+            let cmp = |a: &&Number, b: &&Number| a.float().total_cmp(&b.float());
+            let none = || format!("'{f}' expects at least one argument");
+            match *f {
+                "min" => args.iter().min_by(cmp).copied().ok_or_else(none),
+                "max" => args.iter().max_by(cmp).copied().ok_or_else(none),
+                "abs" => match one()? {
+                    Int(i) => i.checked_abs().map(Int).ok_or_else(overflow),
+                    Float(f) => Ok(Float(f.abs())),
+                },
+                "floor" => Number::to_int(one()?.float().floor(), f),
+                "ceil" => Number::to_int(one()?.float().ceil(), f),
+                "round" => Number::to_int(one()?.float().round(), f),
+                _ => Err(format!("Unknown function '{f}'")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{calculate, Number::*};
+
+    #[test]
+    fn calculate_numbers() {
+        assert_eq!(calculate("1 + 2 * 3 / 4"), Ok(Int(2)));
+        assert_eq!(calculate("1 + 2 * 3 / 4.0"), Ok(Float(2.5)));
+        assert_eq!(calculate("-2 ^ 2 + 2 ^ 3 ^ 2"), Ok(Int(508)));
+        assert_eq!(calculate("7 % 3 + 5! + 0x10 + 0b11"), Ok(Int(140)));
+        assert_eq!(calculate("6 & 3 | 1 << 4 | ~-8 >> 1"), Ok(Int(19)));
+        assert_eq!(calculate("1 < 2 ? 3 != 3 ? 4 : 5 : 6"), Ok(Int(5)));
+        assert_eq!(calculate("max(1, 2.5, -3) + min(4, 5)"), Ok(Float(6.5)));
+        assert_eq!(
+            calculate("floor(2.7) + ceil(2.1) + round(-2.5)"),
+            Ok(Int(2))
+        );
+    }
+
+    #[test]
+    fn calculate_errors() {
+        let error = |e: &str| calculate(e).unwrap_err();
+        assert_eq!(error("1 / 0"), "Division by zero");
+        assert_eq!(error("1.5 % 0"), "Division by zero");
+        assert_eq!(error("(-3)!"), "Factorial of a negative number");
+        assert_eq!(error("1.5!"), "Factorial of a decimal number");
+        assert!(error("21!").starts_with("Integer overflow"));
+        assert!(error("2 ^ 64").starts_with("Integer overflow"));
+        assert_eq!(error("1.5 & 1"), "'&' can only be used with integers");
+        assert_eq!(error("sqrt(4)"), "Unknown function 'sqrt'");
+        assert_eq!(error("floor(1, 2)"), "'floor' expects one argument");
+        assert_eq!(error("max()"), "'max' expects at least one argument");
+        assert!(error("1 +").starts_with("Expected"));
+    }
+}
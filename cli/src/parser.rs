@@ -1,4 +1,6 @@
-use crate::command::Command;
+use crate::command::{
+    is_whole_name, Arg, ArgKind, ArgType, Attribute, Command, Condition, Dependency, Flag, Var,
+};
 use crate::error::Error;
 use crate::lang::Lang;
 
@@ -9,14 +11,56 @@ pub use runfile::runfile;
 use crate::HashMap;
 
 enum Element<'i> {
-    Command(&'i str, Command<'i>),
-    Subcommand(&'i str, Runfile<'i>),
-    Include(&'i str, Runfile<'i>),
-    Constant(&'i str, Str<'i>),
+    /// Command and the position of its name and aliases.
+    Command(&'i str, Command<'i>, usize, usize),
+    /// Subcommand and the position of its name and aliases.
+    Subcommand(&'i str, Runfile<'i>, usize, usize),
+    /// Included runfile and the position of its path.
+    Include(&'i str, Runfile<'i>, usize, usize),
+    Constant(&'i str, Constant<'i>),
+    Env(Vec<(&'i str, Str<'i>)>),
     Error(Error),
     Errors(Vec<Error>),
 }
 
+/// Value of a constant, resolved once the previous constants are known.
+enum Constant<'i> {
+    /// Text and its position.
+    Text(&'i str, usize),
+    /// Math expression and the position of the whole `$(...)`.
+    Math(&'i str, usize, usize),
+    /// Script evaluated by the shell.
+    Shell(&'i str),
+}
+
+/// Attribute as written, checked by `validate_attributes`: `#[name]`, `#[name = value]` or `#[name(a, b)]`
+struct RawAttribute<'i> {
+    name: &'i str,
+    value: AttributeValue<'i>,
+    start: usize,
+    end: usize,
+}
+
+/// What the attributes are written for, each one allows different attributes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Command,
+    Subcommand,
+    /// The whole runfile, with `#![...]` at its start.
+    Runfile,
+}
+
+enum AttributeValue<'i> {
+    Flag,
+    Text(&'i str),
+    List(Vec<&'i str>),
+}
+
+enum Param<'i> {
+    Arg(Arg<'i>),
+    Flag(Flag<'i>),
+}
+
 peg::parser! {
     grammar runfile<'i>() for str {
         rule pos() -> usize = position!()
@@ -33,18 +77,74 @@ peg::parser! {
         } / start:pos() end:pos() {
             Error::PExpectedLangOrCmd(start, end).err()
         }
-        pub rule name() -> Result<&'input str, Error> = __ name:ident() __ {
-            Ok(name)
+        pub rule name() -> Result<(&'input str, Vec<&'input str>), Error> = __ name:ident() aliases:aliases() __ {
+            Ok((name, aliases))
         } / start:pos() end:pos() {
             Error::PExpectedCmdName(start, end).err()
         }
-        pub rule ident() -> &'input str = $(['a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-']+)
-        pub rule arguments() -> Result<Vec<&'input str>, Error> = start:pos() s:"("? v:((ident()) ** " ") " "? e:")"? end:pos() {
+        pub rule aliases() -> Vec<&'input str> = ("|" a:ident() { a })*
+        // Can't start with '-', it would be read as a flag: `-vx`
+        pub rule ident() -> &'input str = $(['a'..='z' | 'A'..='Z' | '0'..='9' | '_'] ['a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-']*)
+        // Choices need at least two values, a single word must be a known type
+        rule arg_type() -> Result<ArgType<'input>, Error> = ":" [' '|'\t']* t:(
+            "int" !choice() { Ok(ArgType::Int) }
+            / "path" !choice() { Ok(ArgType::Path) }
+            / c:$(choice() "|" (choice() ++ "|")) { Ok(ArgType::Choices(c)) }
+            / start:pos() t:$(choice()) end:pos() { Error::PUnknownArgType(t.to_string(), start, end).err() }
+        ) { t }
+        rule choice() = [^' '|'\t'|'\n'|'\r'|'('|')'|'|']+
+        pub rule argument() -> Result<Arg<'input>, Error> = "?" name:ident() t:arg_type()? {
+            Ok(Arg::new(name, ArgKind::Optional).with_type(t.transpose()?.unwrap_or_default()))
+        } / "..." name:ident() t:arg_type()? {
+            Ok(Arg::new(name, ArgKind::Rest).with_type(t.transpose()?.unwrap_or_default()))
+        } / name:ident() "=" v:arg_value() t:arg_type()? {
+            Ok(Arg::new(name, ArgKind::Default(v)).with_type(t.transpose()?.unwrap_or_default()))
+        } / name:ident() t:arg_type()? {
+            Ok(Arg::new(name, ArgKind::Required).with_type(t.transpose()?.unwrap_or_default()))
+        }
+        rule short_flag() -> &'input str = "-" s:$(['a'..='z' | 'A'..='Z' | '0'..='9']) !ident() { s }
+        rule flag_default() -> &'input str = "=" v:arg_value() { v }
+        pub rule flag() -> Flag<'input> = short:short_flag() "/--" long:ident() d:flag_default()? {
+            Flag::new(Some(short), Some(long), d)
+        } / "--" long:ident() d:flag_default()? {
+            Flag::new(None, Some(long), d)
+        } / short:short_flag() d:flag_default()? {
+            Flag::new(Some(short), None, d)
+        }
+        rule parameter() -> Result<Param<'input>, Error> = f:flag() { Ok(Param::Flag(f)) } / a:argument() { a.map(Param::Arg) }
+        // Unquoted values end before the type of the argument: `level=2: int`
+        rule arg_value() -> &'input str = ['"'] v:$(dqc()*) ['"'] { v } / "'" v:$(sqc()*) "'" { v } / $((!(":" [' '|'\t']) [^' '|'\t'|'\n'|'\r'|'('|')'])*)
+        pub rule arguments() -> Result<(Vec<Arg<'input>>, Vec<Flag<'input>>), Error> = start:pos() s:"("? [' '|'\t']* p:(parameter() ** ([' '|'\t']+)) [' '|'\t']* e:")"? end:pos() {
+            let (mut v, mut flags) = (Vec::new(), Vec::new());
+            for p in p {
+                match p? {
+                    Param::Arg(a) => v.push(a),
+                    Param::Flag(f) => flags.push(f),
+                }
+            }
             match (s.is_none(), e.is_none()) {
                 (true, false) => Error::PExpectedOpenParen(start, end).err(),
                 (false, true) => Error::PExpectedCloseParen(start, end).err(),
                 (true, true) => Error::PExpectedArgs(start, end).err(),
-                (false, false) => Ok(v)
+                (false, false) => {
+                    // Rest arguments consume everything, so they must be the last ones
+                    if let Some(rest) = v.iter().rev().skip(1).find(|a| a.kind() == ArgKind::Rest) {
+                        return Error::PRestNotLast(rest.name().to_string(), start, end).err();
+                    }
+                    // Paths are checked when running, they are relative to where 'run' is called
+                    let invalid_default = v.iter().find_map(|a| match a.kind() {
+                        ArgKind::Default(value) if a.ty() != ArgType::Path => a.validate(value.to_owned()).err().map(|reason| (a, reason)),
+                        _ => None,
+                    });
+                    if let Some((a, reason)) = invalid_default {
+                        return Error::PInvalidDefault(a.name().to_string(), reason, start, end).err();
+                    }
+                    // Positional arguments can't be required once one is optional
+                    match v.windows(2).find(|w| !w[0].is_required() && w[1].is_required()) {
+                        Some(w) => Error::PRequiredAfterOptional(w[1].name().to_string(), start, end).err(),
+                        None => Ok((v, flags))
+                    }
+                }
             }
         }
         pub rule body_start() -> usize = s:$['{']+ { s.len() } /*
@@ -53,7 +153,22 @@ peg::parser! {
         } */
         pub rule body_end(count: usize) = ['}']*<{count}>
         pub rule body(count: usize) -> &'input str = $((!(['{'|'}']*<{count}>)[_] / "{"*<1, {(count-1).max(1)}> body((count-1).max(1)) "}"*<1, {(count-1).max(1)}>)*)               // TODO: Remove this atrocity
-        pub rule command() -> Element<'input> = __ doc:doc() __ lang:language() __ name:name() __ args:arguments() __ count:body_start() script:body(count) body_end(count) __ {
+        pub rule dependency() -> Dependency<'input> = start:pos() name:$(ident() ++ ".") end:pos() {
+            Dependency::new(name, start, end)
+        }
+        pub rule dependencies() -> Vec<Dependency<'input>> = ":" [' '|'\t']* d:(dependency() ** ([' '|'\t']+)) [' '|'\t']* { d }
+        rule attribute_value() -> &'input str = ['"'] v:$(dqc()*) ['"'] { v } / $([^ ',' | '(' | ')' | '[' | ']' | '"' | ' ' | '\t' | '\n' | '\r']+)
+        rule attribute() -> RawAttribute<'input> = start:pos() name:ident() value:(
+            [' '|'\t']* "=" [' '|'\t']* v:attribute_value() { AttributeValue::Text(v) }
+            / "(" [' '|'\t']* l:(attribute_value() ** ([' '|'\t']* "," [' '|'\t']*)) [' '|'\t']* ")" { AttributeValue::List(l) }
+        )? end:pos() {
+            RawAttribute { name, value: value.unwrap_or(AttributeValue::Flag), start, end }
+        }
+        rule attribute_list() -> Vec<RawAttribute<'input>> = [' '|'\t']* a:(attribute() ++ ([' '|'\t']* "," [' '|'\t']*)) [' '|'\t']* "]" { a }
+        pub rule attributes() -> Vec<RawAttribute<'input>> = "#[" a:attribute_list() { a }
+        // Written at the start of a runfile, they apply to the whole file: `#![export]`
+        pub rule inner_attributes() -> Vec<RawAttribute<'input>> = a:("#![" a:attribute_list() __ { a })? { a.unwrap_or_default() }
+        pub rule command(dir: &std::path::Path, export: bool) -> Element<'input> = __ doc:doc() __ attributes:(a:attributes() __ { a })* lang:language() __ start:pos() name:name() end:pos() __ args:arguments() __ deps:dependencies()? __ count:body_start() script:body(count) body_end(count) __ {
             let mut errors = Vec::new();
             fn unwrap<T>(result: Result<T, Error>, default: T, errors: &mut Vec<Error>) -> T {
                 match result {
@@ -66,25 +181,36 @@ peg::parser! {
             }
 
             let lang = unwrap(lang, crate::lang::Shell.into(), &mut errors);
-            let name = unwrap(name, "", &mut errors);
-            let args = unwrap(args, Vec::new(), &mut errors);
+            let (name, aliases) = unwrap(name, ("", Vec::new()), &mut errors);
+            let (args, flags) = unwrap(args, (Vec::new(), Vec::new()), &mut errors);
             // unwrap(count, 0, &mut errors);
 
+            let attributes = validate_attributes(attributes.into_iter().flatten(), Target::Command, &mut errors);
+
             if errors.is_empty() {
-                let command = Command::new(name, doc, lang, args, script);
-                Element::Command(name, command)
+                let command = Command::new(name, doc, lang, args, flags, script)
+                    .with_dependencies(deps.unwrap_or_default())
+                    .with_aliases(aliases)
+                    // The runfile's directory, 'cwd' is relative to it
+                    .with_cwd(dir.to_path_buf())
+                    .with_export(export)
+                    .with_attributes(attributes);
+                Element::Command(name, command, start, end)
             } else {
                 Element::Errors(errors)
             }
         }
-        pub rule subcommand(dir: &std::path::Path) -> Element<'input> = __ doc:doc() __ "sub" __ name:ident() __ "{" sub:runfile(dir) "}" __ {
+        pub rule subcommand(dir: &std::path::Path, export: bool) -> Element<'input> = __ doc:doc() __ attributes:(a:attributes() __ { a })* "sub" __ start:pos() name:ident() aliases:aliases() end:pos() __ "{" sub:runfile(dir, export || exports(attributes.iter().flatten())) "}" __ {
+            let mut errors = Vec::new();
+            let attributes = validate_attributes(attributes.into_iter().flatten(), Target::Subcommand, &mut errors);
             match sub {
-                Ok(sub) => Element::Subcommand(name, sub.with_doc(doc)),
+                Ok(_) if !errors.is_empty() => Element::Errors(errors),
+                Ok(sub) => Element::Subcommand(name, sub.with_doc(doc).with_name(name, aliases).with_attributes(attributes), start, end),
                 Err(e) => Element::Errors(e)
             }
         }
 
-        pub rule include(dir: &std::path::Path) -> Element<'input> = __ "in" __ start:pos() name:($([^'\n']+)) end:pos() __ {
+        pub rule include(dir: &std::path::Path, export: bool) -> Element<'input> = __ "in" __ start:pos() name:($([^'\n']+)) end:pos() __ {
             // TODO: Remove leak (should not impact a lot, the string will need to be alive the whole program anyway)
             let path = {
                 if name.starts_with('/') {
@@ -97,51 +223,96 @@ peg::parser! {
                 Ok(file) => file.leak(),
                 Err(e) => return Error::PIncludeRead(e.to_string(), name.to_string(), start, end).into()
             };
-            let include = match runfile::runfile(file, path.parent().unwrap_or(dir)) {
+            let include = match runfile::runfile(file, path.parent().unwrap_or(dir), export) {
                 Ok(include) => include,
                 Err(e) => return Error::PIncludeParse(e.to_string(), name.to_string(), start, end).into(),
             };
+            let name = name.trim_end();
             match include {
-                Ok(include) => Element::Include(name, include),
-                Err(e) => Element::Errors(e)
+                Ok(mut include) => {
+                    include.set_file(name, file);
+                    Element::Include(name, include, start, end)
+                }
+                Err(e) => Element::Errors(e.into_iter().map(|e| Error::PIncluded(Box::new(e), name.to_owned(), file.to_owned())).collect())
             }
         }
         rule dqc() = "\\\"" / [^'"']
         rule sqc() = "\\\'" / [^'\'']
         pub rule value() -> &'input str = ['"'] v:$(dqc()*) ['"'] { v } / "'" v:$(sqc()*) "'" { v } / v:$([^'\n']*)
-        pub rule math() -> Result<Str<'input>, Error> = start:pos() "$(" e:$((!(")"[' ']*"\n") [_])*) ")" end:pos() {
-            match arithmetic::calculate(e.trim()) {
-                Ok(v) => Ok(v.to_string().into()),
-                Err(e) => Error::PMathExpression(start, end).err()
-            }
+        rule spanned_value() -> (&'input str, usize) = ['"'] s:pos() v:$(dqc()*) ['"'] { (v, s) } / "'" s:pos() v:$(sqc()*) "'" { (v, s) } / s:pos() v:$([^'\n']*) { (v, s) }
+        pub rule math() -> (&'input str, usize, usize) = start:pos() "$(" e:$((!(")"[' ']*("\n" / ![_])) [_])*) ")" end:pos() {
+            (e, start, end)
+        }
+        pub rule var() -> Element<'input> = __ "const" _ name:ident() __ "=" __ v:(m:math() { Constant::Math(m.0, m.1, m.2) } / "`" s:$([^'`']*) "`" { Constant::Shell(s) } / v:spanned_value() { Constant::Text(v.0, v.1) }) __ {
+            Element::Constant(name, v)
         }
-        pub rule var() -> Element<'input> = __ "const" _ name:ident() __ "=" __ v:(m:math() { m }/v:value() { Ok(Str::from(v)) }) __ {
-            match v {
-                Ok(v) => Element::Constant(name, v),
-                Err(e) => Element::Error(e)
+        pub rule env() -> Element<'input> = __ "env" _ name:ident() __ "=" __ v:value() __ {
+            Element::Env(vec![(name, Str::from(v))])
+        }
+        pub rule dotenv(dir: &std::path::Path) -> Element<'input> = __ "dotenv" _ start:pos() name:($([^'\n']+)) end:pos() __ {
+            let name = name.trim_end();
+            // Leaked like included files, the variables borrow from it
+            match std::fs::read_to_string(dir.join(name)) {
+                Ok(file) => Element::Env(parse_dotenv(file.leak())),
+                Err(e) => Error::PDotenvRead(e.to_string(), name.to_string(), start, end).into(),
             }
         }
-        pub rule runfile(dir: &std::path::Path) -> Result<Runfile<'input>, Vec<Error>> = __ elements:(var()/include(dir)/subcommand(dir)/command())* __ {
+        // Commands are exported if the runfile starts with `#![export]` or it is inside one
+        pub rule runfile(dir: &std::path::Path, export: bool) -> Result<Runfile<'input>, Vec<Error>> = __ inner:inner_attributes() elements:(var()/env()/dotenv(dir)/include(dir, export || exports(&inner))/subcommand(dir, export || exports(&inner))/command(dir, export || exports(&inner)))* __ {
             let mut commands = HashMap::with_hasher(xxhash_rust::xxh3::Xxh3Builder::new());
             let mut subcommands = HashMap::with_hasher(xxhash_rust::xxh3::Xxh3Builder::new());
+            let mut command_aliases = HashMap::with_hasher(xxhash_rust::xxh3::Xxh3Builder::new());
+            let mut subcommand_aliases = HashMap::with_hasher(xxhash_rust::xxh3::Xxh3Builder::new());
             let mut includes = HashMap::with_hasher(xxhash_rust::xxh3::Xxh3Builder::new());
             let mut vars = Vec::new();
+            let mut env = Vec::new();
             let mut errors = Vec::new();
+            // `export` is the only one, already applied to the commands
+            validate_attributes(inner, Target::Runfile, &mut errors);
             for element in elements {
                 match element {
-                    Element::Command(name, command) => {
-                        commands.insert(name, command);
+                    Element::Command(name, command, start, end) => {
+                        let aliases = command.aliases().to_vec();
+                        if let Err(taken) = insert_named(&mut commands, &mut command_aliases, name, &aliases, command, Command::conditions) {
+                            errors.push(Error::PDuplicateName(taken.to_string(), "command", start, end));
+                        }
                     }
-                    Element::Subcommand(name, sub) => {
-                        subcommands.insert(name, sub);
+                    Element::Subcommand(name, sub, start, end) => {
+                        let aliases = sub.aliases.clone();
+                        if let Err(taken) = insert_named(&mut subcommands, &mut subcommand_aliases, name, &aliases, sub, Runfile::conditions) {
+                            errors.push(Error::PDuplicateName(taken.to_string(), "subcommand", start, end));
+                        }
                     }
-                    Element::Include(name, inc) => {
+                    Element::Include(name, inc, start, end) => {
                         includes.insert(name, inc.clone());
-                        commands.extend(inc.commands);
-                        subcommands.extend(inc.subcommands);
+                        env.extend(inc.env);
+                        vars.extend(inc.vars);
+                        for (name, command) in inc.commands {
+                            let aliases = command.aliases().to_vec();
+                            if let Err(taken) = insert_named(&mut commands, &mut command_aliases, name, &aliases, command, Command::conditions) {
+                                errors.push(Error::PDuplicateName(taken.to_string(), "command", start, end));
+                            }
+                        }
+                        for (name, sub) in inc.subcommands {
+                            let aliases = sub.aliases.clone();
+                            if let Err(taken) = insert_named(&mut subcommands, &mut subcommand_aliases, name, &aliases, sub, Runfile::conditions) {
+                                errors.push(Error::PDuplicateName(taken.to_string(), "subcommand", start, end));
+                            }
+                        }
                     }
                     Element::Constant(name, value) => {
-                        vars.push((name, value));
+                        let value = match value {
+                            Constant::Text(text, start) => resolve_text(text, start, &vars).map(Var::Text),
+                            Constant::Math(expr, start, end) => resolve_math(expr, start, end, &vars).map(Var::Text),
+                            Constant::Shell(script) => Ok(Var::shell(script, dir.to_path_buf())),
+                        };
+                        match value {
+                            Ok(value) => vars.push((name, value)),
+                            Err(e) => errors.push(e),
+                        }
+                    }
+                    Element::Env(variables) => {
+                        env.extend(variables);
                     }
                     Element::Error(e) => {
                         errors.push(e);
@@ -154,49 +325,374 @@ peg::parser! {
             if !errors.is_empty() {
                 return Err(errors);
             }
+            for sub in subcommands.values_mut() {
+                sub.inherit_env(&env);
+            }
             Ok(
                 Runfile {
+                    name: "",
+                    aliases: Vec::new(),
                     doc: String::new(),
                     commands,
                     subcommands,
+                    command_aliases,
+                    subcommand_aliases,
                     includes,
-                    vars
+                    vars,
+                    env,
+                    conditions: Vec::new(),
+                    hidden: false,
                 }
             )
         }
     }
 }
 
-peg::parser!( grammar arithmetic() for str {
-    rule _ = [' ' | '\t' | '\n' | '\r']*
-    pub(crate) rule calculate() -> f64 = precedence!{
-        x:(@) _ "+" _ y:@ { x + y }
-        x:(@) _ "-" _ y:@ { x - y }
-              "-" _ v:@ { - v }
-        --
-        x:(@) _ "*" _ y:@ { x * y }
-        x:(@) _ "/" _  y:@ { x / y }
-        --
-        x:@ _ "^" _ y:(@) { x.powf(y) }
-        v:@ _ "!"       { (1..v as i64+1).product::<i64>() as f64 }
-        --
-        "(" _ v:calculate() _ ")" { v }
-        n:number() { n }
-    }
-
-    rule number() -> f64
-        = n:$(['0'..='9']+"."?['0'..='9']* / ['0'..='9']*"."?['0'..='9']+) { n.parse().unwrap() }
-});
-
 impl From<Error> for Element<'_> {
     fn from(e: Error) -> Self {
         Element::Error(e)
     }
 }
 
+/// Adds a command or subcommand and its aliases to `entries`, with `lookup` pointing each alias to `name`.
+///
+/// The same name can only be declared more than once if the declarations have conditions,
+/// the one that is available is kept.
+/// Returns the name or alias that is already taken otherwise.
+fn insert_named<'i, T>(
+    entries: &mut HashMap<&'i str, T>,
+    lookup: &mut HashMap<&'i str, &'i str>,
+    name: &'i str,
+    aliases: &[&'i str],
+    entry: T,
+    conditions: fn(&T) -> &[Condition<'i>],
+) -> Result<(), &'i str> {
+    let available = |entry: &T| conditions(entry).iter().all(Condition::holds);
+    if let Some(declared) = entries.get(name) {
+        if conditions(declared).is_empty() && conditions(&entry).is_empty() {
+            return Err(name);
+        }
+        if available(declared) && !available(&entry) {
+            return Ok(());
+        }
+    }
+    if lookup.contains_key(name) {
+        return Err(name);
+    }
+    for alias in aliases {
+        let other_entry = *alias != name && entries.contains_key(alias);
+        if other_entry || lookup.get(alias).is_some_and(|n| *n != name) {
+            return Err(alias);
+        }
+    }
+    for alias in aliases {
+        lookup.insert(alias, name);
+    }
+    entries.insert(name, entry);
+    Ok(())
+}
+
+/// Checks the attributes written for `target`, each one can only be written once.
+fn validate_attributes<'i>(
+    written: impl IntoIterator<Item = RawAttribute<'i>>,
+    target: Target,
+    errors: &mut Vec<Error>,
+) -> Vec<Attribute<'i>> {
+    let mut attributes = Vec::new();
+    let mut written_names = Vec::new();
+    for RawAttribute {
+        name,
+        value,
+        start,
+        end,
+    } in written
+    {
+        if written_names.contains(&name) {
+            errors.push(Error::PDuplicateAttribute(name.to_owned(), start, end));
+            continue;
+        }
+        let command = target == Target::Command;
+        let runfile = target == Target::Runfile;
+        let attribute = match (name, value) {
+            ("cwd", AttributeValue::Text(cwd)) if command => Attribute::Cwd(cwd),
+            ("export", AttributeValue::Flag) => Attribute::Export,
+            ("hidden", AttributeValue::Flag) if !runfile => Attribute::Hidden,
+            ("prompt", AttributeValue::Flag) if command => Attribute::Prompt,
+            ("confirm", AttributeValue::Flag) if command => Attribute::Confirm(None),
+            ("confirm", AttributeValue::List(question)) if command && question.len() == 1 => {
+                Attribute::Confirm(Some(question[0]))
+            }
+            ("os", AttributeValue::List(os)) if !runfile && !os.is_empty() => {
+                Attribute::Condition(Condition::Os(os))
+            }
+            ("arch", AttributeValue::List(arch)) if !runfile && !arch.is_empty() => {
+                Attribute::Condition(Condition::Arch(arch))
+            }
+            ("which", AttributeValue::List(programs)) if !runfile && !programs.is_empty() => {
+                Attribute::Condition(Condition::Which(programs))
+            }
+            ("if", AttributeValue::List(condition)) if !runfile => {
+                match env_condition(&condition) {
+                    Some(condition) => Attribute::Condition(condition),
+                    None => {
+                        errors.push(Error::PInvalidAttribute(
+                            name.to_owned(),
+                            "expected '#[if(env.NAME)]' or '#[if(!env.NAME)]'".to_owned(),
+                            start,
+                            end,
+                        ));
+                        continue;
+                    }
+                }
+            }
+            ("cwd" | "confirm" | "prompt", _) if !command => {
+                errors.push(Error::PInvalidAttribute(
+                    name.to_owned(),
+                    "it can only be used on commands".to_owned(),
+                    start,
+                    end,
+                ));
+                continue;
+            }
+            ("hidden" | "os" | "arch" | "which" | "if", _) if runfile => {
+                errors.push(Error::PInvalidAttribute(
+                    name.to_owned(),
+                    "it can only be used on commands and subcommands".to_owned(),
+                    start,
+                    end,
+                ));
+                continue;
+            }
+            (
+                known @ ("cwd" | "export" | "hidden" | "confirm" | "prompt" | "os" | "arch"
+                | "which" | "if"),
+                _,
+            ) => {
+                let usage = match known {
+                    "prompt" => "'#[prompt]'",
+                    "cwd" => "'#[cwd = \"path\"]'",
+                    "confirm" => "'#[confirm]' or '#[confirm(\"question\")]'",
+                    "export" => "'#[export]'",
+                    "hidden" => "'#[hidden]'",
+                    "if" => "'#[if(env.NAME)]' or '#[if(!env.NAME)]'",
+                    _ => "a list like '#[os(linux, macos)]'",
+                };
+                errors.push(Error::PInvalidAttribute(
+                    name.to_owned(),
+                    format!("expected {usage}"),
+                    start,
+                    end,
+                ));
+                continue;
+            }
+            _ => {
+                errors.push(Error::PUnknownAttribute(name.to_owned(), start, end));
+                continue;
+            }
+        };
+        written_names.push(name);
+        attributes.push(attribute);
+    }
+    attributes
+}
+
+/// Whether `export` is written, it is applied to the commands inside while they are parsed.
+fn exports<'a>(attributes: impl IntoIterator<Item = &'a RawAttribute<'a>>) -> bool {
+    attributes.into_iter().any(|a| a.name == "export")
+}
+
+/// Parses the condition of `#[if(env.NAME)]` or `#[if(!env.NAME)]`.
+fn env_condition<'i>(condition: &[&'i str]) -> Option<Condition<'i>> {
+    let [condition] = condition else {
+        return None;
+    };
+    let (negated, condition) = match condition.strip_prefix('!') {
+        Some(condition) => (true, condition),
+        None => (false, *condition),
+    };
+    let name = condition.strip_prefix("env.").filter(|n| !n.is_empty())?;
+    Some(Condition::Env { name, negated })
+}
+
+/// Parses the `NAME=value` lines of a dotenv file, skipping empty lines and `#` comments.
+///
+/// Lines can start with `export`, and values can be surrounded by quotes.
+fn parse_dotenv(file: &str) -> Vec<(&str, Str<'_>)> {
+    file.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| l.strip_prefix("export ").unwrap_or(l).split_once('='))
+        .map(|(name, value)| {
+            let value = value.trim();
+            let value = ['"', '\'']
+                .into_iter()
+                .find_map(|q| value.strip_prefix(q)?.strip_suffix(q))
+                .unwrap_or(value);
+            // Values are taken as written, escaped so they survive the unescaping of runfile values
+            let value = if value.contains(['\\', '$']) {
+                Str::owned(value.replace('\\', "\\\\").replace('$', "\\$"))
+            } else {
+                Str::borrowed(value)
+            };
+            (name.trim(), value)
+        })
+        .collect()
+}
+
+/// Returns the longest declared constant used whole at the start of `s`, like the replacement in commands.
+fn find_constant<'a, 'i>(
+    s: &str,
+    vars: &'a [(&'i str, Var<'i>)],
+) -> Option<&'a (&'i str, Var<'i>)> {
+    // The last one declared wins if there are duplicates
+    vars.iter()
+        .filter(|(name, _)| s.starts_with(name) && is_whole_name(name, &s[name.len()..]))
+        .max_by_key(|(name, _)| name.len())
+}
+
+/// Returns the text of a constant used in another one.
+///
+/// Shell constants are only evaluated when a command uses them, so they can't be used.
+fn constant_text<'a>(
+    name: &str,
+    var: &'a Var<'_>,
+    start: usize,
+    end: usize,
+) -> Result<&'a str, Error> {
+    match var {
+        Var::Text(text) => Ok(text),
+        Var::Shell { .. } => Error::PShellConstant(name.to_owned(), start, end).err(),
+    }
+}
+
+/// Length of the identifier at the start of `s`.
+fn ident_len(s: &str) -> usize {
+    s.find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '-')
+        .unwrap_or(s.len())
+}
+
+/// Replaces the references to previous constants in a text constant, written as `$name` or `${name}`.
+///
+/// `$env.NAME` is expanded when the command runs, and `$$` is a literal `$`.
+fn resolve_text<'i>(
+    text: &'i str,
+    start: usize,
+    vars: &[(&'i str, Var<'i>)],
+) -> Result<Str<'i>, Error> {
+    if !text.contains('$') {
+        return Ok(Str::from(text));
+    }
+    let mut resolved = String::with_capacity(text.len());
+    let mut i = 0;
+    while let Some(next) = text[i..].find('$') {
+        let at = i + next;
+        resolved.push_str(&text[i..at]);
+        let rest = &text[at + 1..];
+        let braced = rest
+            .strip_prefix('{')
+            .and_then(|r| r.find('}').map(|end| &r[..end]));
+        i = at + 1;
+        if text[..at].ends_with('\\') || rest.starts_with("env.") {
+            resolved.push('$');
+        } else if rest.starts_with('$') {
+            // Escaped, so it's not replaced again in the command
+            resolved.push_str("\\$");
+            i += 1;
+        } else if let Some(name) = braced {
+            let (s, e) = (start + at, start + at + name.len() + 3);
+            match vars.iter().rev().find(|(n, _)| *n == name) {
+                Some((_, var)) => resolved.push_str(constant_text(name, var, s, e)?),
+                None => return Error::PUndefinedConstant(name.to_owned(), s, e).err(),
+            }
+            i += name.len() + 2;
+        } else if let Some((name, var)) = find_constant(rest, vars) {
+            let (s, e) = (start + at, start + at + name.len() + 1);
+            resolved.push_str(constant_text(name, var, s, e)?);
+            i += name.len();
+        } else if ident_len(rest) > 0 {
+            let name = &rest[..ident_len(rest)];
+            let (s, e) = (start + at, start + at + name.len() + 1);
+            return Error::PUndefinedConstant(name.to_owned(), s, e).err();
+        } else {
+            resolved.push('$');
+        }
+    }
+    resolved.push_str(&text[i..]);
+    Ok(Str::owned(resolved))
+}
+
+/// Calculates a math expression, where constants can be used by their name.
+fn resolve_math<'i>(
+    expr: &str,
+    start: usize,
+    end: usize,
+    vars: &[(&'i str, Var<'i>)],
+) -> Result<Str<'i>, Error> {
+    // Skip "$("
+    let offset = start + 2;
+    let mut resolved = String::with_capacity(expr.len());
+    let mut chars = expr.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let rest = &expr[i..];
+        let name = rest.strip_prefix('$').unwrap_or(rest);
+        if !(c == '$' || c.is_ascii_alphabetic() || c == '_') {
+            resolved.push(c);
+            // Digits of a number can't start a constant
+            if c.is_ascii_alphanumeric() || c == '.' {
+                while let Some((_, c)) =
+                    chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '.')
+                {
+                    resolved.push(c);
+                }
+            }
+            continue;
+        }
+        // Function calls like `max(a, b)`, constants are written with '$' to be called
+        let word = &name[..name
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(name.len())];
+        if c != '$' && expr[i + word.len()..].trim_start().starts_with('(') {
+            resolved.push_str(word);
+            for _ in 1..word.len() {
+                chars.next();
+            }
+            continue;
+        }
+        let len = match find_constant(name, vars) {
+            Some((name, var)) => {
+                let s = offset + i;
+                let e = s + (c == '$') as usize + name.len();
+                resolved.push('(');
+                resolved.push_str(constant_text(name, var, s, e)?);
+                resolved.push(')');
+                name.len()
+            }
+            None => {
+                let name = &name[..name
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(name.len())];
+                let s = offset + i;
+                let e = s + (c == '$') as usize + name.len();
+                return Error::PUndefinedConstant(name.to_owned(), s, e).err();
+            }
+        };
+        // Skip the rest of the name
+        let skip = len + (c == '$') as usize - 1;
+        for _ in 0..skip {
+            chars.next();
+        }
+    }
+    match crate::math::calculate(&resolved) {
+        Ok(v) => Ok(v.to_string().into()),
+        Err(reason) => Error::PMathExpression(reason, start, end).err(),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::runfile as p;
+    use crate::command::{Arg, ArgKind, ArgType, Flag};
+    use crate::error::Error;
 
     #[test]
     fn doc() {
@@ -215,4 +711,320 @@ mod test {
             Ok("Example hello world in bash\nUsage: sh <NAME>".into())
         );
     }
+
+    #[test]
+    fn name() {
+        assert_eq!(p::name("build"), Ok(Ok(("build", vec![]))));
+        assert_eq!(p::name("build|b|bd"), Ok(Ok(("build", vec!["b", "bd"]))));
+    }
+
+    #[test]
+    fn attributes() {
+        use super::{validate_attributes, Attribute, Condition, Target};
+        let attributes = |input: &'static str, target: Target| {
+            let mut errors = Vec::new();
+            let attributes =
+                validate_attributes(p::attributes(input).unwrap(), target, &mut errors);
+            (attributes, errors)
+        };
+
+        assert_eq!(
+            attributes(
+                "#[cwd = \"../my dir\", export, os(linux, macos)]",
+                Target::Command
+            ),
+            (
+                vec![
+                    Attribute::Cwd("../my dir"),
+                    Attribute::Export,
+                    Attribute::Condition(Condition::Os(vec!["linux", "macos"]))
+                ],
+                vec![]
+            )
+        );
+        assert_eq!(
+            attributes("#[ which(git) , if(!env.CI) ]", Target::Subcommand).0,
+            vec![
+                Attribute::Condition(Condition::Which(vec!["git"])),
+                Attribute::Condition(Condition::Env {
+                    name: "CI",
+                    negated: true
+                })
+            ]
+        );
+        assert_eq!(
+            attributes(
+                "#[cwd = src, timeout, export, export, os(), if(CI)]",
+                Target::Subcommand
+            )
+            .1,
+            vec![
+                Error::PInvalidAttribute(
+                    "cwd".into(),
+                    "it can only be used on commands".into(),
+                    2,
+                    11
+                ),
+                Error::PUnknownAttribute("timeout".into(), 13, 20),
+                Error::PDuplicateAttribute("export".into(), 30, 36),
+                Error::PInvalidAttribute(
+                    "os".into(),
+                    "expected a list like '#[os(linux, macos)]'".into(),
+                    38,
+                    42
+                ),
+                Error::PInvalidAttribute(
+                    "if".into(),
+                    "expected '#[if(env.NAME)]' or '#[if(!env.NAME)]'".into(),
+                    44,
+                    50
+                ),
+            ]
+        );
+        assert_eq!(
+            attributes("#[confirm(\"Sure?\"), hidden]", Target::Command).0,
+            vec![Attribute::Confirm(Some("Sure?")), Attribute::Hidden]
+        );
+        assert_eq!(
+            attributes("#[if = CI]", Target::Command).1,
+            vec![Error::PInvalidAttribute(
+                "if".into(),
+                "expected '#[if(env.NAME)]' or '#[if(!env.NAME)]'".into(),
+                2,
+                9
+            )]
+        );
+        let mut errors = Vec::new();
+        let inner = p::inner_attributes("#![export, hidden]\n").unwrap();
+        assert_eq!(
+            validate_attributes(inner, Target::Runfile, &mut errors),
+            vec![Attribute::Export]
+        );
+        assert_eq!(
+            errors,
+            vec![Error::PInvalidAttribute(
+                "hidden".into(),
+                "it can only be used on commands and subcommands".into(),
+                11,
+                17
+            )]
+        );
+        assert!(p::attributes("#[]").is_err());
+    }
+
+    #[test]
+    fn conditions() {
+        let runfile = p::runfile(
+            "#[os(none)]\ncmd a() {}\n#[os(linux, macos, windows)]\ncmd a() {}\n#[os(none)]\ncmd a() {}",
+            std::path::Path::new(""),
+            false,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(runfile.commands["a"].unmet_condition().is_none());
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let parse = |input| p::runfile(input, std::path::Path::new(""), false).unwrap();
+        let runfile = parse("cmd build|b() {}\nsub frontend|fe {}").unwrap();
+        assert_eq!(runfile.commands.len(), 1);
+        assert_eq!(runfile.command("b").map(|c| c.name()), Some("build"));
+        assert_eq!(runfile.subcommand("fe").map(|s| s.name), Some("frontend"));
+
+        let errors = parse("cmd a() {}\ncmd a() {}").unwrap_err();
+        assert_eq!(
+            errors,
+            [Error::PDuplicateName("a".into(), "command", 15, 16)]
+        );
+        let errors = parse("cmd build|b() {}\ncmd bump|b() {}").unwrap_err();
+        assert_eq!(
+            errors,
+            [Error::PDuplicateName("b".into(), "command", 21, 27)]
+        );
+        let errors = parse("cmd build|b() {}\ncmd b() {}").unwrap_err();
+        assert_eq!(
+            errors,
+            [Error::PDuplicateName("b".into(), "command", 21, 22)]
+        );
+        let errors = parse("sub a {}\nsub b|a {}").unwrap_err();
+        assert_eq!(
+            errors,
+            [Error::PDuplicateName("a".into(), "subcommand", 13, 16)]
+        );
+    }
+
+    #[test]
+    fn hidden() {
+        let runfile = p::runfile(
+            "cmd a() {}\ncmd _b() {}\n#[hidden]\ncmd c() {}\nsub _d {}\n#[hidden]\nsub e {}",
+            std::path::Path::new(""),
+            false,
+        )
+        .unwrap()
+        .unwrap();
+        let listed = runfile.listed_commands().map(|c| c.name());
+        assert_eq!(listed.collect::<Vec<_>>(), ["a"]);
+        assert_eq!(runfile.listed_subcommands().count(), 0);
+        assert_eq!(runfile.commands.len(), 3);
+        assert_eq!(runfile.subcommands.len(), 2);
+    }
+
+    #[test]
+    fn dotenv() {
+        let file = "# Comment\nA=1\n\nexport B = \"two words\"\nC='3'\ninvalid\n";
+        assert_eq!(
+            super::parse_dotenv(file),
+            vec![
+                ("A", "1".into()),
+                ("B", "two words".into()),
+                ("C", "3".into())
+            ]
+        );
+        assert_eq!(
+            super::parse_dotenv("WIN=C:\\new\\tmp\nCOST=$5"),
+            vec![("WIN", r"C:\\new\\tmp".into()), ("COST", r"\$5".into())]
+        );
+    }
+
+    #[test]
+    fn constants() {
+        use super::{resolve_math, resolve_text, Var};
+        let text = |t: &'static str| Var::Text(t.into());
+        let vars = [("a", text("1")), ("a-b", text("2")), ("a", text("3"))];
+
+        assert_eq!(resolve_text("$a-$a-b ${a}c", 0, &vars), Ok("3-2 3c".into()));
+        assert_eq!(
+            resolve_text("$$a \\$a $env.A $ 5", 0, &vars),
+            Ok("\\$a \\$a $env.A $ 5".into())
+        );
+        assert_eq!(
+            resolve_text("x $b", 10, &vars),
+            Err(Error::PUndefinedConstant("b".into(), 12, 14))
+        );
+        assert_eq!(
+            resolve_text("$ab", 0, &vars),
+            Err(Error::PUndefinedConstant("ab".into(), 0, 3))
+        );
+        assert_eq!(resolve_math("a * 2 + $a-b", 0, 0, &vars), Ok("8".into()));
+        assert_eq!(resolve_math("(1 - a) ^ 2", 0, 0, &vars), Ok("4".into()));
+        assert_eq!(
+            resolve_math("max(a, 2) + abs (-a-b)", 0, 0, &vars),
+            Ok("5".into())
+        );
+
+        let runfile = p::runfile(
+            "const A = 3\nconst B = $(max(A, 2) + round(0.6))",
+            std::path::Path::new(""),
+            false,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(runfile.vars[1], ("B", text("4")));
+        assert_eq!(
+            resolve_math("1 + b", 0, 8, &vars),
+            Err(Error::PUndefinedConstant("b".into(), 6, 7))
+        );
+        assert_eq!(
+            resolve_math("ab + 1", 0, 8, &vars),
+            Err(Error::PUndefinedConstant("ab".into(), 2, 4))
+        );
+    }
+
+    #[test]
+    fn dependencies() {
+        use crate::command::Dependency;
+
+        assert_eq!(p::dependencies(":"), Ok(vec![]));
+        assert_eq!(
+            p::dependencies(": build sub.test"),
+            Ok(vec![
+                Dependency::new("build", 2, 7),
+                Dependency::new("sub.test", 8, 16)
+            ])
+        );
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(p::arguments("()"), Ok(Ok((vec![], vec![]))));
+        assert_eq!(
+            p::arguments("(env target=\"prod\" ?verbose)"),
+            Ok(Ok((
+                vec![
+                    Arg::new("env", ArgKind::Required),
+                    Arg::new("target", ArgKind::Default("prod")),
+                    Arg::new("verbose", ArgKind::Optional),
+                ],
+                vec![]
+            )))
+        );
+        assert_eq!(
+            p::arguments("(target=prod)"),
+            Ok(Ok((
+                vec![Arg::new("target", ArgKind::Default("prod"))],
+                vec![]
+            )))
+        );
+        assert_eq!(
+            p::arguments("(pkg ...rest)"),
+            Ok(Ok((
+                vec![
+                    Arg::new("pkg", ArgKind::Required),
+                    Arg::new("rest", ArgKind::Rest),
+                ],
+                vec![]
+            )))
+        );
+        assert_eq!(
+            p::arguments("(env: dev|prod level=2: 1|2|3 ?x:a|b)"),
+            Ok(Ok((
+                vec![
+                    Arg::new("env", ArgKind::Required).with_type(ArgType::Choices("dev|prod")),
+                    Arg::new("level", ArgKind::Default("2")).with_type(ArgType::Choices("1|2|3")),
+                    Arg::new("x", ArgKind::Optional).with_type(ArgType::Choices("a|b")),
+                ],
+                vec![]
+            )))
+        );
+        assert_eq!(
+            p::arguments("(n: int ?file: path ...ints: int)"),
+            Ok(Ok((
+                vec![
+                    Arg::new("n", ArgKind::Required).with_type(ArgType::Int),
+                    Arg::new("file", ArgKind::Optional).with_type(ArgType::Path),
+                    Arg::new("ints", ArgKind::Rest).with_type(ArgType::Int),
+                ],
+                vec![]
+            )))
+        );
+        assert_eq!(
+            p::arguments("(ints: integer)"),
+            Ok(Error::PUnknownArgType("integer".into(), 7, 14).err())
+        );
+        assert_eq!(
+            p::arguments("(n=x: int)"),
+            Ok(Error::PInvalidDefault("n".into(), "expected an integer".into(), 0, 10).err())
+        );
+        assert!(!matches!(p::arguments("(-vx)"), Ok(Ok(_))));
+        assert_eq!(
+            p::arguments("(...rest pkg)"),
+            Ok(Error::PRestNotLast("rest".into(), 0, 13).err())
+        );
+        assert_eq!(
+            p::arguments("(--release -j/--jobs=4 target -v)"),
+            Ok(Ok((
+                vec![Arg::new("target", ArgKind::Required)],
+                vec![
+                    Flag::new(None, Some("release"), None),
+                    Flag::new(Some("j"), Some("jobs"), Some("4")),
+                    Flag::new(Some("v"), None, None),
+                ]
+            )))
+        );
+        assert_eq!(
+            p::arguments("(?verbose env)"),
+            Ok(Error::PRequiredAfterOptional("env".into(), 0, 14).err())
+        );
+    }
 }
@@ -0,0 +1,214 @@
+//! Interactive list to choose a command, filtered as you type.
+
+use std::io::Write as _;
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    queue,
+    terminal::{self, ClearType},
+};
+use yansi::Paint as _;
+
+/// Maximum number of items shown at the same time.
+const VISIBLE: usize = 10;
+
+/// Returns how well `query` matches `text` when its characters appear in order, ignoring case.
+///
+/// The score is the number of characters skipped after the first match, lower is better.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<usize> {
+    let text = text.to_lowercase();
+    let mut chars = text.chars();
+    let mut score = 0;
+    let mut started = false;
+    for q in query.to_lowercase().chars() {
+        loop {
+            let c = chars.next()?;
+            if c == q {
+                started = true;
+                break;
+            }
+            if started {
+                score += 1;
+            }
+        }
+    }
+    Some(score)
+}
+
+/// Returns the indices of the items matching `query`, the best matches first.
+///
+/// Items are matched by name, or by description after all the names.
+fn filter(items: &[(String, String)], query: &str) -> Vec<usize> {
+    let mut matches = items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (name, doc))| {
+            let score = fuzzy_score(query, name)
+                .or_else(|| fuzzy_score(query, doc).map(|s| s + usize::MAX / 2))?;
+            Some((score, i))
+        })
+        .collect::<Vec<_>>();
+    // Stable, items with the same score keep the order of the runfile
+    matches.sort_by_key(|(score, _)| *score);
+    matches.into_iter().map(|(_, i)| i).collect()
+}
+
+/// Disables the raw mode of the terminal when dropped, even if drawing fails.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let mut stderr = std::io::stderr();
+        let _ = queue!(
+            stderr,
+            cursor::MoveToColumn(0),
+            terminal::Clear(ClearType::FromCursorDown)
+        );
+        let _ = stderr.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Lets the user choose one of the items, `(name, description)`, drawn in stderr.
+///
+/// Returns the index of the chosen item, or `None` if the picker was cancelled with `Esc` or `Ctrl+C`.
+pub fn pick(items: &[(String, String)]) -> std::io::Result<Option<usize>> {
+    let _raw = RawMode::enable()?;
+    let mut stderr = std::io::stderr();
+    let mut query = String::new();
+    let mut selected = 0;
+    loop {
+        let matches = filter(items, &query);
+        selected = selected.min(matches.len().saturating_sub(1));
+        draw(&mut stderr, items, &query, &matches, selected)?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if control => return Ok(None),
+            KeyCode::Enter => return Ok(matches.get(selected).copied()),
+            KeyCode::Up => selected = selected.saturating_sub(1),
+            KeyCode::Char('p') if control => selected = selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Tab => selected += 1,
+            KeyCode::Char('n') if control => selected += 1,
+            KeyCode::Backspace => {
+                query.pop();
+                selected = 0;
+            }
+            KeyCode::Char(c) if !control => {
+                query.push(c);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Draws the query followed by the visible matches, leaving the cursor after the query.
+fn draw(
+    to: &mut impl std::io::Write,
+    items: &[(String, String)],
+    query: &str,
+    matches: &[usize],
+    selected: usize,
+) -> std::io::Result<()> {
+    // Some terminals don't report their size
+    let (columns, rows) = match terminal::size()? {
+        (0, _) | (_, 0) => (80, 24),
+        (columns, rows) => (columns as usize, rows as usize),
+    };
+    let visible = VISIBLE.min(rows.saturating_sub(2)).max(1);
+    // Scrolls to keep the selected item visible
+    let first = (selected + 1).saturating_sub(visible);
+    // Lines are cut to the width of the terminal, wrapped lines would break the redraw
+    let columns = columns.saturating_sub(1);
+    let width = matches
+        .iter()
+        .map(|&i| items[i].0.chars().count())
+        .max()
+        .unwrap_or_default()
+        .min(columns.saturating_sub(2));
+
+    queue!(
+        to,
+        cursor::MoveToColumn(0),
+        terminal::Clear(ClearType::FromCursorDown)
+    )?;
+    let count = format!("{}/{}", matches.len(), items.len());
+    let length = query.chars().count();
+    // The end of a long query is kept, it is where the user is typing
+    let query = query
+        .chars()
+        .skip(length.saturating_sub(columns.saturating_sub(2)))
+        .collect::<String>();
+    write!(to, "{} {query}", ">".bright_green().bold())?;
+    if 2 + length + 2 + count.len() <= columns {
+        write!(to, "  {}", count.dim())?;
+    }
+    let shown = matches.iter().enumerate().skip(first).take(visible);
+    let mut lines = 0;
+    for (position, &i) in shown {
+        let (name, doc) = &items[i];
+        let name = name.chars().take(width).collect::<String>();
+        let doc = doc
+            .chars()
+            .take(columns.saturating_sub(width + 4))
+            .collect::<String>();
+        write!(to, "\r\n")?;
+        if position == selected {
+            write!(
+                to,
+                "{} {:width$}  {}",
+                ">".bright_cyan().bold(),
+                name.bright_cyan().bold(),
+                doc.dim()
+            )?;
+        } else {
+            write!(to, "  {name:width$}  {}", doc.dim())?;
+        }
+        lines += 1;
+    }
+    if lines > 0 {
+        queue!(to, cursor::MoveUp(lines))?;
+    }
+    queue!(to, cursor::MoveToColumn(2 + query.chars().count() as u16))?;
+    to.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{filter, fuzzy_score};
+
+    #[test]
+    fn fuzzy() {
+        assert_eq!(fuzzy_score("", "build"), Some(0));
+        assert_eq!(fuzzy_score("bld", "build"), Some(2));
+        assert_eq!(fuzzy_score("BUI", "build"), Some(0));
+        assert_eq!(fuzzy_score("ut", "tests unit"), Some(2));
+        assert_eq!(fuzzy_score("db", "build"), None);
+
+        let items = [
+            ("build".into(), "Builds the project".into()),
+            ("tests unit".into(), "Runs the unit tests".into()),
+            ("bump".into(), "Increases the version".into()),
+            ("deploy".into(), "Uploads the build".into()),
+        ];
+        assert_eq!(filter(&items, ""), [0, 1, 2, 3]);
+        assert_eq!(filter(&items, "bu"), [0, 2, 3]);
+        assert_eq!(filter(&items, "unit"), [1]);
+    }
+}
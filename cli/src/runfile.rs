@@ -1,10 +1,11 @@
 use std::format as f;
-use std::io::Write as _;
+use std::io::{IsTerminal as _, Write as _};
 
 // use colored::{Color, Colorize};
 use yansi::{Color, Paint};
 
-use crate::command::Command;
+use crate::command::{Arguments, Attribute, Command, Condition, Prompt, Var};
+use crate::error::Error;
 use crate::fmt::{
     strlist::{StrList, StrListSlice},
     Str,
@@ -13,12 +14,46 @@ use crate::lang::Language as _;
 use crate::utils::OptionExt;
 use crate::HashMap;
 
+/// Options given to `run` before the command, they apply to the command and its dependencies.
+#[derive(Debug, Clone, Copy)]
+pub struct RunOptions {
+    /// Maximum number of dependencies running at the same time, `-j`.
+    pub jobs: usize,
+    /// Answers yes to the confirmations, `--yes`.
+    pub yes: bool,
+    /// Which arguments are asked for, `--prompt` or the picker.
+    pub prompt: Prompt,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            jobs: 1,
+            yes: false,
+            prompt: Prompt::Off,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct Runfile<'i> {
+    /// Name of the subcommand, empty for the main runfile.
+    pub(crate) name: &'i str,
+    pub(crate) aliases: Vec<&'i str>,
     pub(crate) commands: HashMap<&'i str, Command<'i>>,
     pub(crate) subcommands: HashMap<&'i str, Runfile<'i>>,
+    /// Name of the command each alias refers to.
+    pub(crate) command_aliases: HashMap<&'i str, &'i str>,
+    /// Name of the subcommand each alias refers to.
+    pub(crate) subcommand_aliases: HashMap<&'i str, &'i str>,
     pub(crate) includes: HashMap<&'i str, Runfile<'i>>,
-    pub(crate) vars: Vec<(&'i str, Str<'i>)>,
+    pub(crate) vars: Vec<(&'i str, Var<'i>)>,
+    /// Environment variables exported to the commands, including the ones of the parent runfiles.
+    pub(crate) env: Vec<(&'i str, Str<'i>)>,
+    /// Requirements for the subcommand to be available, also added to all its commands.
+    pub(crate) conditions: Vec<Condition<'i>>,
+    /// Not listed in the help or completions: `#[hidden]`
+    pub(crate) hidden: bool,
     pub(crate) doc: String,
 }
 
@@ -31,16 +66,117 @@ impl<'i> Runfile<'i> {
             .max()
             .unwrap_or_default();
         let second = self
-            .commands
-            .values()
-            .map(|c| c.name().len())
-            .chain(self.subcommands.keys().map(|name| name.len()))
+            .listed_commands()
+            .map(|c| c.names().len())
+            .chain(self.listed_subcommands().map(|s| s.names().len()))
             .max()
             .unwrap_or_default();
 
         (first + 3, second + 1)
     }
 
+    pub fn with_name(mut self, name: &'i str, aliases: Vec<&'i str>) -> Self {
+        self.name = name;
+        self.aliases = aliases;
+        self
+    }
+
+    /// Returns the name followed by the aliases, as shown in the list of subcommands: `frontend, fe`.
+    pub fn names(&self) -> String {
+        StrList::from((
+            ", ",
+            std::iter::once(self.name).chain(self.aliases.iter().copied()),
+        ))
+        .to_string()
+    }
+
+    /// Returns the command called `name`, or the one `name` is an alias of.
+    pub fn command(&self, name: &str) -> Option<&Command<'i>> {
+        let name = self.command_aliases.get(name).copied().unwrap_or(name);
+        self.commands.get(name)
+    }
+
+    /// Returns the subcommand called `name`, or the one `name` is an alias of.
+    pub fn subcommand(&self, name: &str) -> Option<&Runfile<'i>> {
+        let name = self.subcommand_aliases.get(name).copied().unwrap_or(name);
+        self.subcommands.get(name)
+    }
+
+    /// Iterates over the commands shown in the help and completions, skipping the hidden ones.
+    pub fn listed_commands(&self) -> impl Iterator<Item = &Command<'i>> {
+        self.commands.values().filter(|cmd| !cmd.is_hidden())
+    }
+
+    /// Iterates over the subcommands shown in the help and completions, skipping the hidden ones.
+    pub fn listed_subcommands(&self) -> impl Iterator<Item = &Runfile<'i>> {
+        self.subcommands.values().filter(|sub| !sub.is_hidden())
+    }
+
+    /// Hidden subcommands can be called, but are not listed.
+    pub fn is_hidden(&self) -> bool {
+        self.name.starts_with('_') || self.hidden
+    }
+
+    /// Adds the environment variables of a parent runfile, overridden by the ones declared here.
+    pub fn inherit_env(&mut self, env: &[(&'i str, Str<'i>)]) {
+        self.env.splice(0..0, env.iter().cloned());
+        for sub in self.subcommands.values_mut() {
+            sub.inherit_env(env);
+        }
+    }
+
+    /// Applies the attributes written before the subcommand, its conditions are added to everything inside it.
+    ///
+    /// `export` is applied when parsing the commands.
+    pub fn with_attributes(mut self, attributes: Vec<Attribute<'i>>) -> Self {
+        let mut conditions = Vec::new();
+        for attribute in attributes {
+            match attribute {
+                Attribute::Condition(c) => conditions.push(c),
+                Attribute::Hidden => self.hidden = true,
+                Attribute::Export
+                | Attribute::Cwd(_)
+                | Attribute::Confirm(_)
+                | Attribute::Prompt => {}
+            }
+        }
+        self.add_conditions(&conditions);
+        self
+    }
+
+    fn add_conditions(&mut self, conditions: &[Condition<'i>]) {
+        if conditions.is_empty() {
+            return;
+        }
+        self.conditions.extend_from_slice(conditions);
+        for cmd in self.commands.values_mut() {
+            cmd.add_conditions(conditions);
+        }
+        for sub in self.subcommands.values_mut() {
+            sub.add_conditions(conditions);
+        }
+    }
+
+    /// Sets the included file the dependencies of all the commands are written in.
+    pub(crate) fn set_file(&mut self, name: &'i str, input: &'i str) {
+        for cmd in self.commands.values_mut() {
+            cmd.set_file(name, input);
+        }
+        for sub in self.subcommands.values_mut() {
+            sub.set_file(name, input);
+        }
+    }
+
+    /// Returns the requirements for the subcommand to be available.
+    pub fn conditions(&self) -> &[Condition<'i>] {
+        &self.conditions
+    }
+
+    /// Returns the first requirement that is not met, if the subcommand is unavailable.
+    pub fn unmet_condition(&self) -> Option<&Condition<'i>> {
+        self.conditions.iter().find(|c| !c.holds())
+    }
+
     pub fn with_doc(mut self, doc: impl Into<String>) -> Self {
         self.doc = doc.into();
         self
@@ -74,7 +210,7 @@ impl<'i> Runfile<'i> {
     ) -> Result<(), Str<'_>> {
         let op = |e: std::io::Error| Str::from(e.to_string());
 
-        if self.commands.is_empty() {
+        if self.listed_commands().next().is_none() {
             return Ok(());
         }
 
@@ -82,29 +218,49 @@ impl<'i> Runfile<'i> {
         let is_nix = crate::nix::is_nix();
         let mut warnings = Vec::new();
         let (lang_indent, name_indent) = indent;
-        for cmd in self.commands.values() {
+        for cmd in self.listed_commands() {
             let doc = cmd.doc(parents);
             let mut lines = doc.into_iter();
 
             let first = lines.next().unwrap();
             let lang = cmd.lang();
+            let unmet = cmd.unmet_condition();
             // if nix is installed all languages are installed
-            let color = if lang.installed() || is_nix {
+            let color = if unmet.is_some() {
+                Color::BrightBlack
+            } else if lang.installed() || is_nix {
                 Color::Cyan
             } else {
                 warnings.push(lang);
                 Color::BrightYellow
             };
-            let name = cmd.name().bright_cyan().bold();
+            let names = cmd.names();
+            let name = names.paint(if unmet.is_some() {
+                Color::BrightBlack
+            } else {
+                Color::BrightCyan
+            });
             writeln!(
                 to,
                 " {lang:<lang_indent$} {name:<name_indent$} {first}",
-                lang = format!("<{lang}>").paint(color)
+                lang = format!("<{lang}>").paint(color),
+                name = name.bold()
             )
             .map_err(op)?;
             for l in lines {
                 writeln!(to, " {:lang_indent$} {:name_indent$} {}", "", "", l).map_err(op)?;
             }
+            if let Some(condition) = unmet {
+                let unavailable = format!("Unavailable, requires {condition}");
+                writeln!(
+                    to,
+                    " {:lang_indent$} {:name_indent$} {}",
+                    "",
+                    "",
+                    unavailable.dim()
+                )
+                .map_err(op)?;
+            }
         }
 
         if !warnings.is_empty() {
@@ -133,23 +289,38 @@ impl<'i> Runfile<'i> {
         indent: (usize, usize),
         to: &mut (impl std::io::Write + ?Sized),
     ) -> Result<(), Str<'_>> {
-        if self.subcommands.is_empty() {
+        if self.listed_subcommands().next().is_none() {
             return Ok(());
         }
 
         let op = |e: std::io::Error| Str::from(e.to_string());
 
         writeln!(to, "{}", "Subcommands:".bright_green().bold()).map_err(op)?;
-        let subcommands = self.subcommands.iter().collect::<Vec<_>>();
         let indent = indent.0 + indent.1;
-        for (name, sub) in subcommands {
-            let mut doc = sub.doc(name, parents);
-            let name = name.bright_cyan().bold();
-            writeln!(to, " {name:<indent$}  {}", doc.pop_front().unwrap()).map_err(op)?;
+        for sub in self.listed_subcommands() {
+            let mut doc = sub.doc(sub.name, parents);
+            let names = sub.names();
+            let unmet = sub.unmet_condition();
+            let name = names.paint(if unmet.is_some() {
+                Color::BrightBlack
+            } else {
+                Color::BrightCyan
+            });
+            writeln!(
+                to,
+                " {name:<indent$}  {}",
+                doc.pop_front().unwrap(),
+                name = name.bold()
+            )
+            .map_err(op)?;
             for l in doc {
                 // writeln!(to, " {:lang_indent$} {:name_indent$} {l}", "", "").map_err(op)?;
                 writeln!(to, "  {:indent$} {l}", "").map_err(op)?;
             }
+            if let Some(condition) = unmet {
+                let unavailable = format!("Unavailable, requires {condition}");
+                writeln!(to, "  {:indent$} {}", "", unavailable.dim()).map_err(op)?;
+            }
         }
 
         Ok(())
@@ -170,7 +341,7 @@ impl<'i> Runfile<'i> {
             writeln!(to, "{msg}").map_err(op)?;
         }
         writeln!(to, "{}", self.doc("", parents)).map_err(op)?;
-        if !self.commands.is_empty() || !self.subcommands.is_empty() {
+        if self.listed_commands().next().is_some() || self.listed_subcommands().next().is_some() {
             writeln!(to).map_err(op)?;
         }
         self.print_commands(parents, indent, to)?;
@@ -183,11 +354,58 @@ impl<'i> Runfile<'i> {
         &'a self,
         parents: impl Into<StrList<'a>>,
         args: &'a [String],
+        options: RunOptions,
     ) -> Result<(), Str<'a>> {
         let parents = parents.into();
 
         let first = args.first();
-        // Needed for subcommands
+        // Needed for subcommands, the number can also be attached: '-j4' or '--jobs=4'
+        let jobs = first.and_then(|first| match first.as_str() {
+            "-j" | "--jobs" => Some((args.get(1).map(String::as_str), 2)),
+            first => first
+                .strip_prefix("--jobs=")
+                .or_else(|| first.strip_prefix("-j"))
+                .map(|jobs| (Some(jobs), 1)),
+        });
+        if let Some((jobs, skip)) = jobs {
+            let Some(jobs) = jobs.and_then(|j| j.parse().ok()).filter(|&j| j > 0) else {
+                eprintln!(
+                    "{}",
+                    "Error: '--jobs' expects a number of jobs greater than 0"
+                        .bright_red()
+                        .bold()
+                );
+                std::process::exit(1);
+            };
+            let options = RunOptions { jobs, ..options };
+            return self.run(parents, args.get(skip..).unwrap_or_default(), options);
+        }
+        if first.is_some_and_oneof(["-y", "--yes"]) {
+            let options = RunOptions {
+                yes: true,
+                ..options
+            };
+            return self.run(parents, args.get(1..).unwrap_or_default(), options);
+        }
+        if first.is_some_and_oneof(["--pick"]) {
+            if let Some(extra) = args.get(1) {
+                eprintln!(
+                    "{}",
+                    format_args!("Error: '--pick' does not take arguments, got '{extra}'")
+                        .bright_red()
+                        .bold()
+                );
+                std::process::exit(1);
+            }
+            return self.pick(&parents, options);
+        }
+        if first.is_some_and_oneof(["--prompt"]) {
+            let options = RunOptions {
+                prompt: Prompt::Missing,
+                ..options
+            };
+            return self.run(parents, args.get(1..).unwrap_or_default(), options);
+        }
         if first.is_some_and_oneof(["-h", "--help"]) {
             self.print_help(None::<&str>, parents.as_slice(), &mut std::io::stdout())?;
             return Ok(());
@@ -202,15 +420,11 @@ impl<'i> Runfile<'i> {
             return Ok(());
         }
 
-        let runfile_docs = || {
-            let mut buf = Vec::new();
-            self.print_help(None::<&str>, parents.as_slice(), &mut buf)
-                .unwrap_or_default();
-            String::from_utf8(buf).map_err(|e| e.to_string())
-        };
-
         let default = || {
-            let Some(cmd) = self.commands.get("default") else {
+            let Some(cmd) = self.command("default") else {
+                if crate::utils::env_enabled("RUN_PICK") && std::io::stdin().is_terminal() {
+                    return self.pick(&parents, options);
+                }
                 self.print_help(
                     Some(
                         "Error: No command specified and no default command found"
@@ -222,42 +436,642 @@ impl<'i> Runfile<'i> {
                 )?;
                 return Ok(());
             };
-            return cmd
-                .run(parents.as_slice(), args, &self.vars, runfile_docs()?)
-                .map_err(|e| f!("Command execution failed: {}", e).into());
+            self.run_command(&parents, cmd, args, options)
         };
 
         let Some(first) = first.map(String::as_str) else {
             return default();
         };
 
-        if let Some(cmd) = self.commands.get(first) {
-            cmd.run(
-                parents.as_slice(),
+        if let Some(cmd) = self.command(first) {
+            self.run_command(&parents, cmd, args.get(1..).unwrap_or_default(), options)
+        } else if let Some(sub) = self.subcommand(first) {
+            if let Some(condition) = sub.unmet_condition() {
+                eprintln!(
+                    "{}",
+                    format_args!("Error: Subcommand '{first}' is only available with {condition}")
+                        .bright_red()
+                        .bold()
+                );
+                std::process::exit(1);
+            }
+            sub.run(
+                parents.append(sub.name),
                 args.get(1..).unwrap_or_default(),
-                &self.vars,
-                runfile_docs()?,
+                options,
             )
-            .map_err(|e| f!("Command execution failed: {}", e).into())
-        } else if let Some(sub) = self.subcommands.get(first) {
-            sub.run(parents.append(first), args.get(1..).unwrap_or_default())
         } else if self
-            .commands
-            .get("default")
-            .is_some_and(|d| d.args().is_empty())
+            .command("default")
+            .is_some_and(|d| !d.args().is_empty())
         {
+            // The arguments are for the default command
+            default()
+        } else {
+            let error = format!("Error: Could not find command or subcommand '{first}'");
+            let suggestion = match self.suggest(first) {
+                Some(name) => format!("Did you mean '{name}'?\n"),
+                None => String::new(),
+            };
             self.print_help(
-                Some(
-                    format_args!("Error: Could not find command or subcommand '{}'\n", first)
-                        .bright_red()
-                        .bold(),
-                ),
+                Some(format_args!(
+                    "{}\n{}",
+                    error.bright_red().bold(),
+                    suggestion.bright_yellow().bold()
+                )),
                 parents.as_slice(),
                 &mut std::io::stderr(),
             )?;
             std::process::exit(1);
+        }
+    }
+
+    /// Returns the listed command, subcommand or alias closest to `name`, if it is close enough to be a typo.
+    pub fn suggest(&self, name: &str) -> Option<&'i str> {
+        let commands = self
+            .listed_commands()
+            .flat_map(|cmd| std::iter::once(cmd.name()).chain(cmd.aliases().iter().copied()));
+        let subcommands = self
+            .listed_subcommands()
+            .flat_map(|sub| std::iter::once(sub.name).chain(sub.aliases.iter().copied()));
+        commands
+            .chain(subcommands)
+            .map(|candidate| (crate::utils::edit_distance(name, candidate), candidate))
+            // Up to a third of the characters can be mistyped
+            .filter(|(distance, candidate)| *distance <= candidate.chars().count().div_ceil(3))
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, candidate)| candidate)
+    }
+
+    /// Returns the path of every available command that is listed, including the ones in subcommands: `sub.cmd`
+    pub fn listed_paths(&self) -> Vec<(String, &Command<'i>)> {
+        let commands = self
+            .listed_commands()
+            .filter(|cmd| cmd.unmet_condition().is_none())
+            .map(|cmd| (cmd.name().to_owned(), cmd));
+        let subcommands = self
+            .listed_subcommands()
+            .filter(|sub| sub.unmet_condition().is_none())
+            .flat_map(|sub| {
+                let paths = sub.listed_paths().into_iter();
+                paths.map(|(path, cmd)| (f!("{}.{path}", sub.name), cmd))
+            });
+        commands.chain(subcommands).collect()
+    }
+
+    /// Asks which of the listed commands to run with the picker, then asks for its arguments.
+    ///
+    /// Exits if stdin or stderr are not terminals.
+    fn pick<'a>(&'a self, parents: &StrList<'a>, options: RunOptions) -> Result<(), Str<'a>> {
+        let fail = |msg: &dyn std::fmt::Display| -> ! {
+            eprintln!("{}", format_args!("Error: {msg}").bright_red().bold());
+            std::process::exit(1);
+        };
+        if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+            fail(&"The command picker needs a terminal");
+        }
+        let paths = self.listed_paths();
+        if paths.is_empty() {
+            fail(&"There are no commands to pick from");
+        }
+        let items = paths
+            .iter()
+            .map(|(path, cmd)| {
+                let doc = cmd.doc_raw().lines().next().unwrap_or_default();
+                (path.replace('.', " "), doc.to_owned())
+            })
+            .collect::<Vec<_>>();
+        let picked = crate::picker::pick(&items).unwrap_or_else(|e| fail(&e));
+        let Some((path, _)) = picked.and_then(|i| paths.get(i)) else {
+            return Ok(());
+        };
+
+        let Some((path, runfile, cmd)) = self.find(path) else {
+            return Err(f!("Could not find command '{path}'").into());
+        };
+        // Every argument and flag is asked for too, with its default
+        let options = RunOptions {
+            prompt: Prompt::All,
+            ..options
+        };
+        runfile.run_command(&Self::plan_parents(parents, &path), cmd, &[], options)
+    }
+
+    /// Runs `cmd` after all its dependencies, running up to `options.jobs` dependencies at the same time.
+    fn run_command<'a>(
+        &'a self,
+        parents: &StrList<'a>,
+        cmd: &'a Command<'i>,
+        args: &'a [String],
+        options: RunOptions,
+    ) -> Result<(), Str<'a>> {
+        if args.iter().any(|a| a == "--help" || a == "-h") {
+            cmd.print_help(parents.as_slice(), 0, &mut std::io::stdout())
+                .map_err(|e| Str::from(e.to_string()))?;
+            return Ok(());
+        }
+        let mut plan = Vec::new();
+        self.plan(cmd.name(), cmd, &mut plan);
+
+        // Check every argument and ask everything before running anything
+        let fail = |e: Str| -> ! {
+            eprintln!("{e}");
+            std::process::exit(1);
+        };
+        let cmd_args = cmd
+            .parse_args(parents.as_slice(), args, options.prompt)
+            .unwrap_or_else(|e| fail(e));
+        let plan = plan
+            .into_iter()
+            .map(|(path, runfile, dep)| {
+                let parents = Self::plan_parents(parents, &path);
+                let args = dep
+                    .parse_args(parents.as_slice(), &[], Prompt::Off)
+                    .unwrap_or_else(|e| fail(e));
+                (path, runfile, dep, args)
+            })
+            .collect::<Vec<_>>();
+        for (_, _, dep, _) in &plan {
+            Self::confirm(dep, options.yes);
+        }
+        Self::confirm(cmd, options.yes);
+
+        if options.jobs > 1 {
+            Self::run_parallel(parents, &plan, options.jobs)?;
         } else {
-            default()
+            for (path, runfile, dep, args) in &plan {
+                let parents = Self::plan_parents(parents, path);
+                dep.run(
+                    parents.as_slice(),
+                    args,
+                    &runfile.vars,
+                    &runfile.env,
+                    runfile.docs(parents.as_slice())?,
+                );
+            }
+        }
+
+        cmd.run(
+            parents.as_slice(),
+            &cmd_args,
+            &self.vars,
+            &self.env,
+            self.docs(parents.as_slice())?,
+        );
+        Ok(())
+    }
+
+    /// Asks before running a command with the `confirm` attribute, unless `--yes` or `RUN_YES=1` are used.
+    ///
+    /// Exits if the answer is not yes, or if stdin is not a terminal.
+    fn confirm(cmd: &Command, yes: bool) {
+        let Some(question) = cmd.confirmation() else {
+            return;
+        };
+        if yes || crate::utils::env_enabled("RUN_YES") {
+            return;
+        }
+        let stdin = std::io::stdin();
+        if !stdin.is_terminal() {
+            eprintln!(
+                "{}",
+                format_args!(
+                    "Error: '{}' needs confirmation but stdin is not a terminal, use '--yes' or 'RUN_YES=1' to run it",
+                    cmd.name()
+                )
+                .bright_red()
+                .bold()
+            );
+            std::process::exit(1);
+        }
+        eprint!("{} {} ", question.bright_yellow().bold(), "[y/N]".dim());
+        let mut answer = String::new();
+        if stdin.read_line(&mut answer).is_err()
+            || !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+        {
+            eprintln!("{}", "Cancelled".bright_red().bold());
+            std::process::exit(1);
         }
     }
+
+    /// Returns `parents` followed by the subcommands in `path`.
+    fn plan_parents<'a>(parents: &StrList<'a>, path: &str) -> StrList<'a> {
+        let subs = path.split('.').map(|s| Str::owned(s.to_owned()));
+        parents.clone().extend(subs.clone().take(subs.count() - 1))
+    }
+
+    /// Returns the subcommands in `path` followed by '.', the prefix of the dependencies found from it.
+    fn path_prefix(path: &str) -> String {
+        path.rsplit_once('.')
+            .map(|(p, _)| f!("{p}."))
+            .unwrap_or_default()
+    }
+
+    /// Runs the commands in `plan` with up to `jobs` of them at the same time.
+    ///
+    /// A command starts once all its dependencies have finished, it is prepared (and compiled) in its own thread
+    /// and its output is prefixed with its path.<br>
+    /// If one fails or `run` is interrupted, the running ones are killed along with the programs they started,
+    /// the pending ones are never started and `run` exits.
+    fn run_parallel(
+        parents: &StrList<'_>,
+        plan: &[(String, &Runfile<'i>, &Command<'i>, Arguments<'i>)],
+        jobs: usize,
+    ) -> Result<(), Str<'static>> {
+        type Job = (std::process::Child, [std::thread::JoinHandle<()>; 2]);
+        enum State<'s> {
+            Pending,
+            Preparing(std::thread::ScopedJoinHandle<'s, Result<Job, Str<'static>>>),
+            Running(Job),
+            Done,
+        }
+
+        // Indices of the dependencies of each command, always before it in the plan
+        let deps = plan
+            .iter()
+            .map(|(path, runfile, cmd, _)| {
+                let prefix = Self::path_prefix(path);
+                cmd.dependencies()
+                    .iter()
+                    .filter_map(|d| runfile.find(d.name()))
+                    .filter_map(|(d, ..)| plan.iter().position(|(p, ..)| *p == prefix.clone() + &d))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let width = plan.iter().map(|(p, ..)| p.len()).max().unwrap_or_default();
+        let colors = [
+            Color::Cyan,
+            Color::Magenta,
+            Color::Yellow,
+            Color::Green,
+            Color::Blue,
+            Color::BrightRed,
+        ];
+
+        // The commands don't get Ctrl+C from the terminal in their own process group, they are killed instead
+        // Handlers can't be removed, `done` gives the signals their default action back once finished
+        let interrupted = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let handlers = [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM].map(|signal| {
+            signal_hook::flag::register_conditional_default(signal, done.clone())?;
+            signal_hook::flag::register(signal, interrupted.clone())
+        });
+
+        // Waits for the commands being prepared, so none is left running
+        let cancel = |states: &mut [State]| {
+            for state in states {
+                match std::mem::replace(state, State::Done) {
+                    State::Preparing(thread) => {
+                        if let Ok(Ok((mut child, _))) = thread.join() {
+                            kill(&mut child);
+                        }
+                    }
+                    State::Running((mut child, _)) => kill(&mut child),
+                    State::Pending | State::Done => {}
+                }
+            }
+        };
+
+        std::thread::scope(|scope| {
+            let mut states = plan.iter().map(|_| State::Pending).collect::<Vec<_>>();
+            loop {
+                if interrupted.load(std::sync::atomic::Ordering::Relaxed) {
+                    cancel(&mut states);
+                    std::process::exit(130);
+                }
+
+                // Start every command whose dependencies have finished
+                for (i, (path, runfile, cmd, args)) in plan.iter().enumerate() {
+                    let running = states
+                        .iter()
+                        .filter(|s| matches!(s, State::Preparing(_) | State::Running(_)))
+                        .count();
+                    let ready = deps[i].iter().all(|&d| matches!(states[d], State::Done));
+                    if running >= jobs || !ready || !matches!(states[i], State::Pending) {
+                        continue;
+                    }
+
+                    let parents = Self::plan_parents(parents, path);
+                    let prefix = f!("[{path:>width$}]")
+                        .paint(colors[i % colors.len()])
+                        .to_string();
+                    states[i] = State::Preparing(scope.spawn(move || {
+                        let docs = runfile.docs(parents.as_slice())?;
+                        let program = cmd.prepare(
+                            parents.as_slice(),
+                            args,
+                            &runfile.vars,
+                            &runfile.env,
+                            docs,
+                        )?;
+                        spawn_prefixed(program, prefix)
+                    }));
+                }
+
+                if states.iter().all(|s| matches!(s, State::Done)) {
+                    done.store(true, std::sync::atomic::Ordering::Relaxed);
+                    for id in handlers.into_iter().flatten() {
+                        signal_hook::low_level::unregister(id);
+                    }
+                    return Ok(());
+                }
+
+                for (i, (path, _, cmd, _)) in plan.iter().enumerate() {
+                    let finished = match &mut states[i] {
+                        State::Preparing(thread) => thread.is_finished(),
+                        State::Running((child, _)) => !matches!(child.try_wait(), Ok(None)),
+                        State::Pending | State::Done => false,
+                    };
+                    if !finished {
+                        continue;
+                    }
+                    let status = match std::mem::replace(&mut states[i], State::Done) {
+                        State::Preparing(thread) => match thread.join() {
+                            Ok(Ok(job)) => {
+                                states[i] = State::Running(job);
+                                continue;
+                            }
+                            Ok(Err(e)) => Err(e),
+                            Err(_) => Err("Failed to prepare the command".into()),
+                        },
+                        State::Running((child, output)) => {
+                            for thread in output {
+                                thread.join().ok();
+                            }
+                            crate::lang::wait_for_child(child)
+                        }
+                        State::Pending | State::Done => unreachable!(),
+                    };
+                    if let Err(e) = status {
+                        cancel(&mut states);
+                        cmd.print_error(Self::plan_parents(parents, path).as_slice(), e);
+                        std::process::exit(1);
+                    }
+                }
+
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+        })
+    }
+
+    /// Returns the help message of the runfile, available as `$doc` in the commands.
+    fn docs(&self, parents: StrListSlice) -> Result<String, Str<'static>> {
+        let mut buf = Vec::new();
+        self.print_help(None::<&str>, parents, &mut buf)
+            .unwrap_or_default();
+        String::from_utf8(buf).map_err(|e| e.to_string().into())
+    }
+
+    /// Returns the command at `path`, with subcommands separated by '.', and the runfile containing it.
+    ///
+    /// The returned path has every alias replaced by its primary name.
+    pub fn find<'a>(&'a self, path: &str) -> Option<(String, &'a Runfile<'i>, &'a Command<'i>)> {
+        let (subs, name) = match path.rsplit_once('.') {
+            Some((subs, name)) => (subs.split('.').collect(), name),
+            None => (Vec::new(), path),
+        };
+        let mut runfile = self;
+        let mut canonical = String::new();
+        for sub in subs {
+            runfile = runfile.subcommand(sub)?;
+            canonical = canonical + runfile.name + ".";
+        }
+        let cmd = runfile.command(name)?;
+        Some((canonical + cmd.name(), runfile, cmd))
+    }
+
+    /// Adds the dependencies of `cmd` to `plan` in execution order, each one appearing once.
+    ///
+    /// `path` is the path of `cmd` relative to the runfile where the plan started.
+    fn plan<'a>(
+        &'a self,
+        path: &str,
+        cmd: &'a Command<'i>,
+        plan: &mut Vec<(String, &'a Runfile<'i>, &'a Command<'i>)>,
+    ) {
+        let prefix = Self::path_prefix(path);
+        for dep in cmd.dependencies() {
+            let Some((dep_path, runfile, next)) = self.find(dep.name()) else {
+                continue;
+            };
+            let next_path = prefix.clone() + &dep_path;
+            if plan.iter().any(|(p, ..)| *p == next_path) {
+                continue;
+            }
+            runfile.plan(&next_path, next, plan);
+            plan.push((next_path, runfile, next));
+        }
+    }
+
+    /// Checks that every dependency exists, has no required arguments and is not part of a cycle.
+    pub fn check_dependencies(&self) -> Result<(), Vec<Error>> {
+        let mut errors = Vec::new();
+        self.check_dependencies_in("", &mut Vec::new(), &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn check_dependencies_in<'a>(
+        &'a self,
+        prefix: &str,
+        done: &mut Vec<&'a Command<'i>>,
+        errors: &mut Vec<Error>,
+    ) {
+        for cmd in self.commands.values() {
+            for dep in cmd.dependencies() {
+                let (start, end) = dep.span();
+                match self.find(dep.name()) {
+                    None => errors.push(dep.error(Error::PUnknownDependency(
+                        dep.name().to_owned(),
+                        start,
+                        end,
+                    ))),
+                    Some((_, _, d)) if d.required_args() > 0 => errors
+                        .push(dep.error(Error::PDependencyArgs(dep.name().to_owned(), start, end))),
+                    Some(_) => {}
+                }
+            }
+            self.find_cycles(
+                f!("{prefix}{}", cmd.name()),
+                cmd,
+                &mut Vec::new(),
+                done,
+                errors,
+            );
+        }
+        for sub in self.subcommands.values() {
+            sub.check_dependencies_in(&f!("{prefix}{}.", sub.name), done, errors);
+        }
+    }
+
+    /// Depth-first search reporting every dependency that closes a cycle.
+    fn find_cycles<'a>(
+        &'a self,
+        path: String,
+        cmd: &'a Command<'i>,
+        stack: &mut Vec<(&'a Command<'i>, String)>,
+        done: &mut Vec<&'a Command<'i>>,
+        errors: &mut Vec<Error>,
+    ) {
+        if done.iter().any(|d| std::ptr::eq(*d, cmd)) {
+            return;
+        }
+        let prefix = Self::path_prefix(&path);
+        stack.push((cmd, path));
+        for dep in cmd.dependencies() {
+            let Some((dep_path, runfile, next)) = self.find(dep.name()) else {
+                continue;
+            };
+            if let Some(i) = stack.iter().position(|(c, _)| std::ptr::eq(*c, next)) {
+                let cycle = stack[i..]
+                    .iter()
+                    .chain([&stack[i]])
+                    .map(|(_, p)| p.as_str());
+                let (start, end) = dep.span();
+                errors.push(dep.error(Error::PDependencyCycle(
+                    StrList::from((" -> ", cycle)).to_string(),
+                    start,
+                    end,
+                )));
+                continue;
+            }
+            runfile.find_cycles(prefix.clone() + &dep_path, next, stack, done, errors);
+        }
+        stack.pop();
+        done.push(cmd);
+    }
+}
+
+/// Kills a command started by `spawn_prefixed`, and every program it started in its process group.
+fn kill(child: &mut std::process::Child) {
+    #[cfg(unix)]
+    // SAFETY: Only sends a signal, the group has not been reaped as the child has not been waited for
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    child.kill().ok();
+    child.wait().ok();
+}
+
+/// Spawns `program` with its output prefixed by `prefix` on each line.
+fn spawn_prefixed(
+    mut program: std::process::Command,
+    prefix: String,
+) -> Result<(std::process::Child, [std::thread::JoinHandle<()>; 2]), Str<'static>> {
+    use std::io::BufRead as _;
+    use std::process::Stdio;
+
+    // Its own process group, so the programs it starts can be killed with it
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut program, 0);
+    let mut child = program
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| crate::lang::execution_failed(program.get_program().to_string_lossy(), e))?;
+
+    let stdout = child.stdout.take().map(std::io::BufReader::new);
+    let stderr = child.stderr.take().map(std::io::BufReader::new);
+    let out_prefix = prefix.clone();
+    let stdout = std::thread::spawn(move || {
+        for line in stdout
+            .into_iter()
+            .flat_map(|s| s.lines().map_while(Result::ok))
+        {
+            println!("{out_prefix} {line}");
+        }
+    });
+    let stderr = std::thread::spawn(move || {
+        for line in stderr
+            .into_iter()
+            .flat_map(|s| s.lines().map_while(Result::ok))
+        {
+            eprintln!("{prefix} {line}");
+        }
+    });
+
+    Ok((child, [stdout, stderr]))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::Error;
+    use crate::parser::runfile;
+
+    fn parse(input: &str) -> super::Runfile<'_> {
+        runfile(input, std::path::Path::new(""), false)
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn check_dependencies() {
+        let check = |input| parse(input).check_dependencies();
+        assert_eq!(
+            check("cmd a(): a {}"),
+            Err(vec![Error::PDependencyCycle("a -> a".into(), 9, 10)])
+        );
+        assert_eq!(
+            check("cmd a(): b {}\ncmd b(): a {}"),
+            Err(vec![Error::PDependencyCycle("a -> b -> a".into(), 23, 24)])
+        );
+        // Reached from 'a', reported where it closes
+        assert_eq!(
+            check("cmd a(): s.b {}\nsub s {\n  cmd b(): c {}\n  cmd c(): b {}\n}"),
+            Err(vec![Error::PDependencyCycle(
+                "s.b -> s.c -> s.b".into(),
+                51,
+                52
+            )])
+        );
+        assert_eq!(
+            check("cmd a(): b {}"),
+            Err(vec![Error::PUnknownDependency("b".into(), 9, 10)])
+        );
+        assert_eq!(
+            check("cmd a(): b c {}\ncmd b(): d {}\ncmd c(): d {}\ncmd d() {}"),
+            Ok(())
+        );
+        // Spans of included files are relative to their own contents
+        let input = "cmd a(): b {}";
+        let mut included = parse(input);
+        included.set_file("inc.run", input);
+        assert_eq!(
+            included.check_dependencies(),
+            Err(vec![Error::PIncluded(
+                Box::new(Error::PUnknownDependency("b".into(), 9, 10)),
+                "inc.run".into(),
+                input.into()
+            )])
+        );
+    }
+
+    #[test]
+    fn plan() {
+        let plan = |input, name| {
+            let runfile = parse(input);
+            let mut plan = Vec::new();
+            runfile.plan(name, runfile.command(name).unwrap(), &mut plan);
+            plan.into_iter().map(|(path, ..)| path).collect::<Vec<_>>()
+        };
+        // Shared dependencies run once, before everything that needs them
+        assert_eq!(
+            plan(
+                "cmd a(): b c {}\ncmd b(): d {}\ncmd c(): d {}\ncmd d() {}",
+                "a"
+            ),
+            ["d", "b", "c"]
+        );
+        assert_eq!(
+            plan(
+                "cmd a(): s.b {}\nsub s {\n  cmd b(): c {}\n  cmd c() {}\n}",
+                "a"
+            ),
+            ["s.c", "s.b"]
+        );
+    }
 }
@@ -1,3 +1,4 @@
+#[allow(dead_code)]
 pub(crate) trait Check<T> {
     fn check(self) -> Option<T>
     where
@@ -14,6 +15,7 @@ impl Check<()> for bool {
     }
 }
 
+#[allow(dead_code)]
 pub trait Goodbye<T>
 where
     Self: Sized,
@@ -79,6 +81,7 @@ pub trait OptionExt<T> {
     where
         T: PartialEq<U>;
 
+    #[allow(dead_code)]
     fn drop_and<U>(self, and: U) -> Option<U>;
 }
 
@@ -100,6 +103,7 @@ impl<T> OptionExt<T> for Option<T> {
     }
 }
 
+#[allow(dead_code)]
 pub trait BoolExt: Check<()>
 where
     Self: Sized,
@@ -128,3 +132,41 @@ where
 }
 
 impl BoolExt for bool {}
+
+/// Returns `true` if the environment variable is set to something other than empty or `0`: `RUN_YES=1`
+pub fn env_enabled(name: &str) -> bool {
+    std::env::var_os(name).is_some_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Number of characters that must be inserted, removed or replaced to turn `a` into `b`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    // Distances from the current prefix of `a` to every prefix of `b`
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + usize::from(ca != *cb))
+                .min(row[j] + 1)
+                .min(above + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::edit_distance;
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("build", "build"), 0);
+        assert_eq!(edit_distance("buld", "build"), 1);
+        assert_eq!(edit_distance("biuld", "build"), 2);
+        assert_eq!(edit_distance("", "run"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}